@@ -0,0 +1,280 @@
+// Arbitrary precision fixed point numbers.
+// Used for deep zoom calculations, where f64 doesn't have
+// enough precision to hold the fractal centre point.
+
+use num_bigint::BigInt;
+use num_traits::{ToPrimitive, Zero};
+use serde::de::Error;
+use serde::{Deserialize, Deserializer, Serialize};
+
+// Largest decimal exponent accepted when parsing, well beyond any
+// usable zoom, so that settings files can't ask for huge powers of 10.
+const MAX_EXP10: i64 = 4000;
+
+// Fixed point number with value mant / 2^prec.
+// Numbers combined in arithmetic must have the same precision.
+#[derive(Debug, Clone, PartialEq)]
+pub struct BigFixed {
+    mant: BigInt,
+    prec: u32,
+}
+
+impl BigFixed {
+    // Zero at the given precision (bits after the binary point).
+    pub fn zero(prec: u32) -> Self {
+        BigFixed { mant: BigInt::zero(), prec }
+    }
+
+    // Parse a decimal string, e.g. "-1.25", "3e-20", "0.0001234E+2".
+    // Returns None if the string isn't a valid decimal number,
+    // or its exponent is beyond MAX_EXP10.
+    pub fn parse(s: &str, prec: u32) -> Option<Self> {
+        let s = s.trim();
+
+        // Split off the sign.
+        let (negative, s) = match s.strip_prefix('-') {
+            Some(rest) => (true, rest),
+            None => (false, s.strip_prefix('+').unwrap_or(s)),
+        };
+
+        // Split off the exponent.
+        let (num, exp10) = match s.find(['e', 'E']) {
+            Some(idx) => (&s[..idx], s[idx + 1..].parse::<i64>().ok()?),
+            None => (s, 0),
+        };
+        if exp10.abs() > MAX_EXP10 {
+            return None;
+        }
+
+        // Digits either side of the decimal point.
+        let (int_part, frac_part) = match num.find('.') {
+            Some(idx) => (&num[..idx], &num[idx + 1..]),
+            None => (num, ""),
+        };
+        if int_part.is_empty() && frac_part.is_empty() {
+            return None;
+        }
+        if !int_part.chars().chain(frac_part.chars()).all(|ch| ch.is_ascii_digit()) {
+            return None;
+        }
+
+        // Value is digits x 10^exp10.
+        let digits = format!("{}{}", int_part, frac_part);
+        let digits = BigInt::parse_bytes(digits.as_bytes(), 10)?;
+        let exp10 = exp10 - frac_part.len() as i64;
+
+        // Scale by 2^prec, dividing by any negative power of 10 last
+        // so that no precision is lost.
+        let scaled = digits << prec;
+        let mant = if exp10 >= 0 {
+            scaled * pow10(exp10 as u32)
+        } else {
+            let divisor = pow10((-exp10) as u32);
+            (scaled + (&divisor >> 1)) / divisor
+        };
+
+        Some(BigFixed {
+            mant: if negative { -mant } else { mant },
+            prec,
+        })
+    }
+
+    // Exact conversion from f64.
+    pub fn from_f64(value: f64, prec: u32) -> Self {
+        if value == 0.0 || !value.is_finite() {
+            return BigFixed::zero(prec);
+        }
+
+        // Decompose into integer mantissa and binary exponent.
+        let bits = value.abs().to_bits();
+        let exp_bits = ((bits >> 52) & 0x7ff) as i64;
+        let (mantissa, exp2) = if exp_bits == 0 {
+            (bits & 0xf_ffff_ffff_ffff, -1074)
+        } else {
+            ((bits & 0xf_ffff_ffff_ffff) | (1 << 52), exp_bits - 1075)
+        };
+
+        let mant = BigInt::from(mantissa);
+        let shift = exp2 + prec as i64;
+        let mant = if shift >= 0 { mant << shift as u64 } else { mant >> (-shift) as u64 };
+
+        BigFixed {
+            mant: if value < 0.0 { -mant } else { mant },
+            prec,
+        }
+    }
+
+    // Nearest f64 value.
+    // Values too small for f64 become zero.
+    pub fn to_f64(&self) -> f64 {
+        // Keep the top 64 bits of the mantissa, and scale by the rest.
+        let bits = self.mant.bits() as i64;
+        let shift = (bits - 64).max(0);
+        let top = (&self.mant >> shift as u64).to_f64().unwrap_or(0.0);
+        top * 2f64.powi((shift - self.prec as i64) as i32)
+    }
+
+    // Sum of two numbers.
+    pub fn add(&self, other: &BigFixed) -> BigFixed {
+        BigFixed { mant: &self.mant + &other.mant, prec: self.prec }
+    }
+
+    // Difference of two numbers.
+    pub fn sub(&self, other: &BigFixed) -> BigFixed {
+        BigFixed { mant: &self.mant - &other.mant, prec: self.prec }
+    }
+
+    // Product of two numbers, truncated to the precision.
+    pub fn mul(&self, other: &BigFixed) -> BigFixed {
+        BigFixed { mant: (&self.mant * &other.mant) >> self.prec, prec: self.prec }
+    }
+
+    // Product with an integer.
+    pub fn mul_int(&self, value: i64) -> BigFixed {
+        BigFixed { mant: &self.mant * value, prec: self.prec }
+    }
+}
+
+// Power of 10 as BigInt.
+fn pow10(exp: u32) -> BigInt {
+    BigInt::from(10).pow(exp)
+}
+
+// Decimal number held exactly as a string, for settings files.
+// Written as a string, but numbers are also accepted when reading
+// so that older settings files can still be read.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(transparent)]
+pub struct DecimalString(pub String);
+
+impl DecimalString {
+    // Checked decimal string.
+    // Returns None if the string isn't a valid decimal number, or is beyond
+    // f64 range, as the f64 value is still used outside the deep zoom orbit.
+    // Values that would round to zero as f64 are also beyond range.
+    pub fn parse(s: &str) -> Option<Self> {
+        let s = s.trim();
+        BigFixed::parse(s, 64)?;
+        let value: f64 = s.parse().ok()?;
+        let mantissa = s.split(['e', 'E']).next().unwrap_or("");
+        let is_zero = !mantissa.chars().any(|ch| ('1'..='9').contains(&ch));
+        if !value.is_finite() || (value == 0.0 && !is_zero) {
+            return None;
+        }
+        Some(DecimalString(s.to_string()))
+    }
+
+    // Nearest f64 value, in range for checked strings.
+    pub fn to_f64(&self) -> f64 {
+        self.0.parse().unwrap_or(0.0)
+    }
+}
+
+impl<'de> Deserialize<'de> for DecimalString {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        #[derive(Deserialize)]
+        #[serde(untagged)]
+        enum RawDecimal {
+            Str(String),
+            Float(f64),
+            Int(i64),
+        }
+
+        let value = match RawDecimal::deserialize(deserializer)? {
+            RawDecimal::Str(value) => value.trim().to_string(),
+            RawDecimal::Float(value) => value.to_string(),
+            RawDecimal::Int(value) => value.to_string(),
+        };
+
+        DecimalString::parse(&value)
+            .ok_or_else(|| D::Error::custom(format!("invalid decimal number, or beyond f64 range: {:?}", value)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PREC: u32 = 64;
+
+    fn parse_f64(s: &str) -> Option<f64> {
+        BigFixed::parse(s, PREC).map(|value| value.to_f64())
+    }
+
+    // Values that aren't exact in binary are within a bit or so of the f64.
+    fn assert_near(s: &str, expected: f64) {
+        let value = parse_f64(s).unwrap();
+        assert!((value - expected).abs() <= expected.abs() * 1e-15, "{:?} gave {}", s, value);
+    }
+
+    #[test]
+    fn parse_signs() {
+        assert_eq!(parse_f64("1.5"), Some(1.5));
+        assert_eq!(parse_f64("+1.5"), Some(1.5));
+        assert_eq!(parse_f64("-1.5"), Some(-1.5));
+        assert_eq!(parse_f64(" -0.25 "), Some(-0.25));
+        assert_eq!(parse_f64("-0"), Some(0.0));
+    }
+
+    #[test]
+    fn parse_fractions() {
+        assert_eq!(parse_f64("3"), Some(3.0));
+        assert_eq!(parse_f64("3."), Some(3.0));
+        assert_eq!(parse_f64(".75"), Some(0.75));
+        assert_near("0.0001", 0.0001);
+        assert_eq!(BigFixed::parse("0.5", 1), Some(BigFixed { mant: BigInt::from(1), prec: 1 }));
+    }
+
+    #[test]
+    fn parse_exponents() {
+        assert_eq!(parse_f64("1e3"), Some(1000.0));
+        assert_eq!(parse_f64("1E+3"), Some(1000.0));
+        assert_eq!(parse_f64("2.5e-1"), Some(0.25));
+        assert_near("2.5e-2", 0.025);
+        assert_near("-0.0001234E+2", -0.01234);
+
+        // Same value however the digits and exponent are split.
+        let tiny = BigFixed::parse("1.5e-30", 256).unwrap();
+        assert_eq!(BigFixed::parse("15e-31", 256).unwrap(), tiny);
+        assert_eq!(BigFixed::parse("0.0000000000000000000000000000015", 256).unwrap(), tiny);
+    }
+
+    #[test]
+    fn parse_exponent_limit() {
+        assert!(BigFixed::parse("1e4000", PREC).is_some());
+        assert!(BigFixed::parse("1e-4000", PREC).is_some());
+        assert_eq!(BigFixed::parse("1e4001", PREC), None);
+        assert_eq!(BigFixed::parse("1e-4001", PREC), None);
+        assert_eq!(BigFixed::parse("1e999999999", PREC), None);
+        assert_eq!(BigFixed::parse("1e99999999999999999999", PREC), None);
+    }
+
+    #[test]
+    fn parse_bad_input() {
+        for bad in ["", " ", "-", "+", ".", "e5", "1e", "1e+", "1.2.3", "1,5", "--1", "+-1",
+                    "abc", "1x", "0x10", "1 2", "inf", "NaN", "1e2.5"] {
+            assert_eq!(BigFixed::parse(bad, PREC), None, "{:?}", bad);
+        }
+    }
+
+    #[test]
+    fn decimal_string_range() {
+        assert_eq!(DecimalString::parse(" -1.5e-300 ").map(|value| value.to_f64()), Some(-1.5e-300));
+        assert_eq!(DecimalString::parse("0.000e-999").map(|value| value.to_f64()), Some(0.0));
+        assert_eq!(DecimalString::parse("1e-400"), None);
+        assert_eq!(DecimalString::parse("1e400"), None);
+        assert_eq!(DecimalString::parse("1.2.3"), None);
+    }
+
+    #[test]
+    fn decimal_string_rejects_huge_exponent() {
+        #[derive(Deserialize)]
+        struct Config {
+            #[allow(dead_code)]
+            value: DecimalString,
+        }
+        assert!(toml::from_str::<Config>("value = \"1e-20\"").is_ok());
+        assert!(toml::from_str::<Config>("value = 0.5").is_ok());
+        assert!(toml::from_str::<Config>("value = \"1e999999999\"").is_err());
+    }
+}
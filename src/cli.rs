@@ -0,0 +1,210 @@
+// Command line interface.
+// Non-interactive alternative to the application menu,
+// so that fractals can be rendered from scripts.
+// Commands use the menu functions that don't prompt the user,
+// for calculating, setting the palete and saving files.
+
+use log::{error, info};
+
+use std::io;
+use std::process::ExitCode;
+
+use crate::fractal::Fractal;
+use crate::menu;
+use crate::palete;
+
+// Exit codes.
+// Usage errors are distinct from failures while running a command.
+const EXIT_FAILURE: u8 = 1;
+const EXIT_USAGE: u8 = 2;
+
+// Command line usage.
+const USAGE: &str = "\
+Usage: fractals [COMMAND] [OPTIONS]
+
+With no command the interactive menu is started.
+
+Commands:
+  render     --config <file.toml|image.png> --out <image.png> [--recalc]
+             [--palete <name|file>]
+             Render fractal image from a settings file, optionally
+             with a built-in palete or palete file instead of the
+             palete in the settings.
+  histogram  --config <file.toml|image.png> --out <plot.png> [--recalc]
+             Plot iterations histogram from a settings file.
+  info       --config <file.toml|image.png>
+             Print the fractal settings from a settings file.
+  paletes    List the built-in colour paletes.
+  help       Print this message.
+
+Settings can be read from a TOML settings file, or from a PNG
+image previously rendered by this program.
+Results saved with the settings file are used if present,
+otherwise (or with --recalc) divergence is calculated first.";
+
+// Options common to the commands.
+#[derive(Default)]
+struct CliOptions {
+    config: Option<String>,
+    out: Option<String>,
+    palete: Option<String>,
+    recalc: bool,
+}
+
+// Parse the options following the command.
+fn parse_options(args: &[String]) -> Result<CliOptions, String> {
+    let mut options = CliOptions::default();
+    let mut args_iter = args.iter();
+
+    while let Some(arg) = args_iter.next() {
+        match arg.as_str() {
+            "--config" => options.config = Some(option_value(arg, args_iter.next())?),
+            "--out" => options.out = Some(option_value(arg, args_iter.next())?),
+            "--palete" => options.palete = Some(option_value(arg, args_iter.next())?),
+            "--recalc" => options.recalc = true,
+            _ => return Err(format!("Unknown option: {}", arg)),
+        }
+    }
+
+    Ok(options)
+}
+
+// Value following an option that requires one.
+fn option_value(option: &str, value: Option<&String>) -> Result<String, String> {
+    match value {
+        Some(value) if !value.starts_with("--") => Ok(value.clone()),
+        _ => Err(format!("Missing value for option: {}", option)),
+    }
+}
+
+// Option that must be supplied for the command.
+fn required(value: &Option<String>, option: &str) -> Result<String, String> {
+    value.clone().ok_or(format!("Missing required option: {}", option))
+}
+
+// Run the command given on the command line.
+// args excludes the program name.
+pub fn run(args: &[String], fractals: &mut Fractal) -> ExitCode {
+    let command = args[0].as_str();
+    let options = match parse_options(&args[1..]) {
+        Ok(options) => options,
+        Err(e) => return usage_error(&e),
+    };
+
+    info!("Command line command: {}", command);
+
+    match command {
+        "render" => {
+            let (config, out) = match (required(&options.config, "--config"), required(&options.out, "--out")) {
+                (Ok(config), Ok(out)) => (config, out),
+                (Err(e), _) | (_, Err(e)) => return usage_error(&e),
+            };
+            if let Err(code) = load_and_calculate(fractals, &config, options.recalc) {
+                return code;
+            }
+            if let Some(palete) = &options.palete {
+                if let Err(e) = menu::set_palete(fractals, palete) {
+                    return failure(&format!("Failed to set palete {:?}, not a built-in palete ({}) or palete file: {}",
+                        palete, palete::builtin_palete_names(), e));
+                }
+            }
+            // Settings without a palete are rendered with the default, as interactively.
+            menu::set_default_palete(fractals);
+            match menu::save_image(fractals, &out) {
+                Ok(()) => {
+                    println!("Image saved to: {} ({:?})", out, fractals.render_duration);
+                    ExitCode::SUCCESS
+                }
+                Err(e) => failure(&format!("Failed to save image {:?}: {}", out, e)),
+            }
+        }
+
+        "histogram" => {
+            let (config, out) = match (required(&options.config, "--config"), required(&options.out, "--out")) {
+                (Ok(config), Ok(out)) => (config, out),
+                (Err(e), _) | (_, Err(e)) => return usage_error(&e),
+            };
+            if let Err(code) = load_and_calculate(fractals, &config, options.recalc) {
+                return code;
+            }
+            match menu::plot_histogram(fractals, &out) {
+                Ok(()) => {
+                    println!("Histogram saved to: {}", out);
+                    ExitCode::SUCCESS
+                }
+                Err(e) => failure(&format!("Failed to generate histogram {:?}: {}", out, e)),
+            }
+        }
+
+        "info" => {
+            let config = match required(&options.config, "--config") {
+                Ok(config) => config,
+                Err(e) => return usage_error(&e),
+            };
+            if let Err(e) = load_any_config(fractals, &config, true) {
+                return failure(&format!("Failed to read from file {:?}: {}", config, e));
+            }
+            menu::print_class(fractals);
+            println!("Results        : {}", if fractals.has_results { "loaded" } else { "none" });
+            ExitCode::SUCCESS
+        }
+
+        "paletes" => {
+            for builtin in palete::BUILTIN_PALETES {
+                println!("{:<12} ({} interpolation)", builtin.name, builtin.interp);
+            }
+            ExitCode::SUCCESS
+        }
+
+        "help" | "--help" | "-h" => {
+            println!("{}", USAGE);
+            ExitCode::SUCCESS
+        }
+
+        _ => usage_error(&format!("Unknown command: {}", command)),
+    }
+}
+
+// Load fractal settings file, and calculate divergence if there
+// are no saved results or recalculation is requested, in which
+// case the results file isn't read.
+fn load_and_calculate(fractals: &mut Fractal, config: &str, recalc: bool) -> Result<(), ExitCode> {
+    if let Err(e) = load_any_config(fractals, config, !recalc) {
+        return Err(failure(&format!("Failed to read from file {:?}: {}", config, e)));
+    }
+    if recalc || !fractals.has_results {
+        menu::cal_divergence(fractals);
+        if !fractals.has_results {
+            return Err(failure("Failed to calculate fractal divergence"));
+        }
+    }
+    Ok(())
+}
+
+// Load fractal settings from a PNG image or TOML settings file,
+// and any results saved with a settings file if with_results.
+// Results that fail to load are warned about, and left to be recalculated.
+fn load_any_config(fractals: &mut Fractal, config: &str, with_results: bool) -> io::Result<()> {
+    if config.to_lowercase().ends_with(".png") {
+        fractals.load_image_config(config)
+    } else {
+        if let Some(warning) = fractals.load_config(config, with_results)? {
+            eprintln!("{}", warning);
+        }
+        Ok(())
+    }
+}
+
+// Report a usage error.
+fn usage_error(msg: &str) -> ExitCode {
+    error!("{}", msg);
+    eprintln!("{}\n\n{}", msg, USAGE);
+    ExitCode::from(EXIT_USAGE)
+}
+
+// Report a failure running a command.
+fn failure(msg: &str) -> ExitCode {
+    error!("{}", msg);
+    eprintln!("{}", msg);
+    ExitCode::from(EXIT_FAILURE)
+}
@@ -0,0 +1,355 @@
+// Colouring modes for rendering images.
+// Modes map the fractional divergence of each pixel to the
+// value looked up in the colour palete.
+// Interior modes colour points inside the set from data about
+// their orbit, otherwise they are the interior colour.
+// Distance modes colour points outside the set from the estimated
+// distance to the set boundary, in pixels, instead of the divergence.
+
+use serde::{Deserialize, Serialize};
+use std::f32::consts;
+use std::fmt;
+
+use crate::fractal::Fractal;
+use crate::orbit_trap::OrbitTrap;
+
+// Selection of colouring mode.
+// This is what is saved to file under the colour_mode key.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Default)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ColourMode {
+    // Divergence used directly against the palete boundaries.
+    #[default]
+    Linear,
+    // Divergence mapped through the cumulative distribution of
+    // iteration counts, so colours are spread evenly over the pixels.
+    HistogramEq,
+    // Palete repeated every period of divergence, starting at the offset.
+    // With log scaling the divergence is replaced by ln(1 + divergence),
+    // and the period and offset are in those units.
+    Cyclic { period: f32, offset: f32, log_scale: bool },
+}
+
+impl ColourMode {
+    // Colouring mode from the menu number, with the cycle for cyclic.
+    // Returns None if the menu number isn't a colouring mode,
+    // or the cycle period isn't greater than 0.
+    pub fn from_menu(choice: u32, period: f32, offset: f32, log_scale: bool) -> Option<ColourMode> {
+        match choice {
+            1 => Some(ColourMode::Linear),
+            2 => Some(ColourMode::HistogramEq),
+            3 if period > 0.0 && period.is_finite() && offset.is_finite() => {
+                Some(ColourMode::Cyclic { period, offset, log_scale })
+            }
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for ColourMode {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ColourMode::Linear => write!(f, "Linear"),
+            ColourMode::HistogramEq => write!(f, "Histogram equalised"),
+            ColourMode::Cyclic { period, offset, log_scale } => {
+                write!(f, "Cyclic (period {}, offset {}{})", period, offset, if *log_scale { ", log scale" } else { "" })
+            }
+        }
+    }
+}
+
+// Selection of interior colouring mode.
+// Data for the mode is calculated with the divergence, as the interior value.
+// This is what is saved to file under the interior_mode key.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum InteriorMode {
+    // Interior colour for every point.
+    #[default]
+    Solid,
+    // Magnitude of the final orbit point, |z|.
+    Magnitude,
+    // Period of the orbit cycle, found by the interior checks.
+    Period,
+    // Mean angle of the orbit points.
+    Angle,
+}
+
+impl InteriorMode {
+    // Interior mode from the menu number.
+    // Returns None if the menu number isn't an interior mode.
+    pub fn from_menu(choice: u32) -> Option<InteriorMode> {
+        match choice {
+            1 => Some(InteriorMode::Solid),
+            2 => Some(InteriorMode::Magnitude),
+            3 => Some(InteriorMode::Period),
+            4 => Some(InteriorMode::Angle),
+            _ => None,
+        }
+    }
+
+    // True if points known to be inside the set can skip iterating.
+    // Final magnitude and mean angle need the orbit.
+    pub fn allows_short_cut(&self) -> bool {
+        matches!(self, InteriorMode::Solid | InteriorMode::Period)
+    }
+}
+
+impl fmt::Display for InteriorMode {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            InteriorMode::Solid => write!(f, "Solid"),
+            InteriorMode::Magnitude => write!(f, "Final |z| magnitude"),
+            InteriorMode::Period => write!(f, "Orbit period"),
+            InteriorMode::Angle => write!(f, "Mean orbit angle"),
+        }
+    }
+}
+
+// Selection of distance rendering mode.
+// Distance to the set is estimated with the divergence, so the
+// derivative of the orbit is only tracked if the mode is on.
+// This is what is saved to file under the distance_mode key.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Default)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum DistanceMode {
+    #[default]
+    Off,
+    // Palete end colour for points within the width (pixels) of the boundary,
+    // otherwise the first palete colour, for line art.
+    Outline { width: f32 },
+    // Palete from the end colour at the boundary, fading with distance,
+    // down to a third at the scale (pixels).
+    Fade { scale: f32 },
+    // Divergence colouring, with pixels less than a pixel from the boundary
+    // blended with the interior colour, for a smooth edge.
+    AntiAlias,
+}
+
+impl DistanceMode {
+    // Distance mode from the menu number, with the width or scale.
+    // Returns None if the menu number isn't a distance mode.
+    pub fn from_menu(choice: u32, size: f32) -> Option<DistanceMode> {
+        match choice {
+            1 => Some(DistanceMode::Off),
+            2 => Some(DistanceMode::Outline { width: size }),
+            3 => Some(DistanceMode::Fade { scale: size }),
+            4 => Some(DistanceMode::AntiAlias),
+            _ => None,
+        }
+    }
+
+    // True if distance to the set needs to be calculated.
+    pub fn is_on(&self) -> bool {
+        *self != DistanceMode::Off
+    }
+}
+
+impl fmt::Display for DistanceMode {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            DistanceMode::Off => write!(f, "Off"),
+            DistanceMode::Outline { width } => write!(f, "Outline (width {} pixels)", width),
+            DistanceMode::Fade { scale } => write!(f, "Fade (scale {} pixels)", scale),
+            DistanceMode::AntiAlias => write!(f, "Anti-aliased edge"),
+        }
+    }
+}
+
+// Number of periods before the interior palete repeats.
+const PERIOD_COLOURS: u32 = 12;
+
+// Smallest step above the first palete boundary for mapped values.
+const PAL_LO_MARGIN: f32 = 1.0e-3;
+
+// Mapping from fractional divergence to palete value,
+// built once from the results for rendering an image.
+pub struct ColourMap {
+    mode: ColourMode,
+    interior_mode: InteriorMode,
+    distance_mode: DistanceMode,
+    orbit_trap: OrbitTrap,
+    max_its: f32,
+    // Cumulative distribution of iteration counts, for histogram equalisation.
+    // cdf[its] is the fraction of pixels outside the set below its iterations.
+    cdf: Vec<f32>,
+    // Palete range the distribution or cycle is spread over.
+    pal_lo: f32,
+    pal_hi: f32,
+    // Palete range interior values are spread over, the whole palete.
+    pal_end: f32,
+}
+
+impl ColourMap {
+    pub fn new(fractals: &Fractal) -> Self {
+        let mut colour_map = ColourMap {
+            mode: fractals.colour_mode,
+            interior_mode: fractals.interior_mode,
+            distance_mode: fractals.distance_mode,
+            orbit_trap: fractals.orbit_trap.clone(),
+            max_its: fractals.max_its as f32,
+            cdf: Vec::new(),
+            pal_lo: fractals.col_palete.first().map_or(0.0, |bound| bound.0 as f32),
+            pal_hi: fractals.max_its as f32,
+            pal_end: fractals.col_palete.last().map_or(0.0, |bound| bound.0 as f32),
+        };
+        match colour_map.mode {
+            ColourMode::Linear => (),
+            ColourMode::HistogramEq => colour_map.cdf = cal_cdf(fractals),
+            // Cycles cover the whole palete.
+            ColourMode::Cyclic { .. } => colour_map.pal_hi = colour_map.pal_end,
+        }
+        colour_map
+    }
+
+    // Palete value for a pixel fractional divergence.
+    // Points inside the set stay at maximum iterations.
+    pub fn map(&self, mu: f32) -> f32 {
+        match self.mode {
+            ColourMode::Linear => mu,
+            ColourMode::HistogramEq => {
+                if mu >= self.max_its || self.cdf.len() < 2 {
+                    return mu;
+                }
+
+                // Interpolate the distribution for the fractional part.
+                let its = (mu.max(0.0) as usize).min(self.cdf.len() - 2);
+                let frac = (mu - its as f32).clamp(0.0, 1.0);
+                let level = self.cdf[its] + frac * (self.cdf[its + 1] - self.cdf[its]);
+                self.pal_value(level)
+            }
+            ColourMode::Cyclic { period, offset, log_scale } => {
+                if mu >= self.max_its || period <= 0.0 {
+                    return mu;
+                }
+
+                // Position in the cycle.
+                let value = if log_scale { mu.max(0.0).ln_1p() } else { mu };
+                let level = ((value + offset) / period).rem_euclid(1.0);
+                self.pal_value(level)
+            }
+        }
+    }
+
+    // Palete value for a point inside the set from its interior value.
+    // Returns None for solid interior, or where there is no interior data
+    // (orbit period not found), for the interior colour.
+    pub fn map_interior(&self, value: f32) -> Option<f32> {
+        if value.is_nan() {
+            return None;
+        }
+        let level = match self.interior_mode {
+            InteriorMode::Solid => return None,
+            // Orbit stays within |z| of 2.
+            InteriorMode::Magnitude => (value / 2.0).clamp(0.0, 1.0),
+            InteriorMode::Period => {
+                if value < 1.0 {
+                    return None;
+                }
+                ((value as u32 - 1) % PERIOD_COLOURS) as f32 / PERIOD_COLOURS as f32
+            }
+            InteriorMode::Angle => ((value + consts::PI) / consts::TAU).clamp(0.0, 1.0),
+        };
+        Some(self.pal_end_value(level))
+    }
+
+    // Palete value for a point outside the set from its distance to the set.
+    // Returns None if the distance mode doesn't replace the divergence.
+    pub fn map_distance(&self, dist: f32) -> Option<f32> {
+        let level = match self.distance_mode {
+            DistanceMode::Off | DistanceMode::AntiAlias => return None,
+            // One pixel soft edge to the outline.
+            DistanceMode::Outline { width } => (width + 1.0 - dist).clamp(0.0, 1.0),
+            DistanceMode::Fade { scale } => {
+                if scale <= 0.0 {
+                    return None;
+                }
+                (-dist / scale).exp()
+            }
+        };
+        Some(self.pal_end_value(level))
+    }
+
+    // Palete value for a point outside the set from the minimum distance
+    // of its orbit to the orbit trap.
+    // Returns None if there is no orbit trap, or the orbit missed it.
+    pub fn map_trap(&self, trap_dist: f32) -> Option<f32> {
+        self.orbit_trap.level(trap_dist).map(|level| self.pal_end_value(level))
+    }
+
+    // Palete value for a level from 0 to 1 over the palete range.
+    fn pal_value(&self, level: f32) -> f32 {
+        // Keep above the first palete boundary, which the palete lookup excludes.
+        let pal_value = self.pal_lo + level * (self.pal_hi - self.pal_lo);
+        pal_value.max(self.pal_lo + PAL_LO_MARGIN)
+    }
+
+    // Palete value for a level from 0 to 1 over the whole palete.
+    fn pal_end_value(&self, level: f32) -> f32 {
+        let pal_value = self.pal_lo + level * (self.pal_end - self.pal_lo);
+        pal_value.max(self.pal_lo + PAL_LO_MARGIN)
+    }
+}
+
+// Cumulative distribution of iteration counts for pixels outside the set.
+fn cal_cdf(fractals: &Fractal) -> Vec<f32> {
+    let max_its = fractals.max_its as usize;
+    let mut counts: Vec<u64> = vec![0; max_its + 1];
+    for row in &fractals.escape_its {
+        for its in row {
+            if (*its as usize) < max_its {
+                counts[*its as usize] += 1;
+            }
+        }
+    }
+
+    let total: u64 = counts.iter().sum::<u64>().max(1);
+    let mut cdf: Vec<f32> = Vec::with_capacity(max_its + 1);
+    let mut cumulative: u64 = 0;
+    for count in counts {
+        cdf.push(cumulative as f32 / total as f32);
+        cumulative += count;
+    }
+    cdf
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fractal::test_fractal;
+    use crate::menu::cal_divergence;
+
+    // Cyclic colouring from the menu needs a period greater than 0.
+    #[test]
+    fn colour_mode_from_menu() {
+        assert_eq!(ColourMode::from_menu(1, 0.0, 0.0, false), Some(ColourMode::Linear));
+        assert_eq!(ColourMode::from_menu(2, 0.0, 0.0, false), Some(ColourMode::HistogramEq));
+        assert_eq!(ColourMode::from_menu(3, 20.0, 5.0, true),
+            Some(ColourMode::Cyclic { period: 20.0, offset: 5.0, log_scale: true }));
+        assert_eq!(ColourMode::from_menu(3, 0.0, 0.0, false), None);
+        assert_eq!(ColourMode::from_menu(3, -10.0, 0.0, false), None);
+        assert_eq!(ColourMode::from_menu(3, f32::NAN, 0.0, false), None);
+        assert_eq!(ColourMode::from_menu(4, 20.0, 0.0, false), None);
+    }
+
+    // Histogram equalisation distribution rises from 0 to 1 over the iteration counts.
+    #[test]
+    fn histogram_cdf() {
+        let mut fractals = test_fractal(r#"
+            rows = 60
+            cols = 80
+            mid_pt = ["-0.6", "0"]
+            pt_div = "0.035"
+            max_its = 200
+            col_palete = []
+        "#);
+        cal_divergence(&mut fractals);
+        fractals.colour_mode = ColourMode::HistogramEq;
+
+        let cdf = ColourMap::new(&fractals).cdf;
+        assert_eq!(cdf.len(), fractals.max_its as usize + 1);
+        assert_eq!(cdf[0], 0.0);
+        assert!(cdf.windows(2).all(|pair| pair[0] <= pair[1]));
+        assert_eq!(cdf[cdf.len() - 1], 1.0);
+    }
+}
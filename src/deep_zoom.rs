@@ -0,0 +1,184 @@
+// Deep zoom calculation of fractal divergence using perturbation.
+// One reference orbit is calculated at the fractal centre in high
+// precision, then each pixel is iterated in f64 as a small delta
+// from the reference orbit, so the zoom depth isn't limited by f64.
+// Only for the Mandelbrot formula (and its Julia sets).
+
+use num_complex::Complex;
+
+use crate::bigfixed::BigFixed;
+
+// Reference orbit, rounded to f64 for the pixel iterations.
+pub struct RefOrbit {
+    pub z: Vec<Complex<f64>>,
+}
+
+// Pixel iteration results.
+pub struct DeltaResult {
+    pub num_its: u32,
+    pub px_fn: Complex<f64>,
+    pub dz: Complex<f64>,
+    pub rebased: bool,
+}
+
+// Bits of precision needed for the reference orbit,
+// enough to resolve the point division with spare bits.
+pub fn precision_bits(pt_div: f64) -> u32 {
+    let div_bits = if pt_div > 0.0 { (-pt_div.log2()).ceil().max(0.0) as u32 } else { 0 };
+    div_bits + 64
+}
+
+// Calculate the reference orbit at the centre point.
+// For Mandelbrot the centre is the constant and the orbit starts at 0,
+// for Julia sets the centre is the start and the constant is julia_c.
+// Orbit stops when it diverges (modulus squared at bailout_sqr)
+// or reaches the maximum iterations.
+pub fn cal_ref_orbit(centre: (&str, &str), pt_div: f64, julia_c: Option<Complex<f64>>, max_its: u32,
+    bailout_sqr: f64) -> Result<RefOrbit, String> {
+    let prec = precision_bits(pt_div);
+    let parse = |s: &str| BigFixed::parse(s, prec).ok_or(format!("Invalid decimal number: {:?}", s));
+    let centre_re = parse(centre.0)?;
+    let centre_im = parse(centre.1)?;
+
+    // Starting point and constant.
+    let (mut z_re, mut z_im, c_re, c_im) = match julia_c {
+        Some(c) => (centre_re, centre_im, BigFixed::from_f64(c.re, prec), BigFixed::from_f64(c.im, prec)),
+        None => (BigFixed::zero(prec), BigFixed::zero(prec), centre_re, centre_im),
+    };
+
+    let mut orbit: Vec<Complex<f64>> = Vec::with_capacity(max_its as usize + 1);
+    orbit.push(Complex::new(z_re.to_f64(), z_im.to_f64()));
+
+    for _ in 0..max_its {
+        // Zn+1 = Zn^2 + C in high precision.
+        let re_sq = z_re.mul(&z_re);
+        let im_sq = z_im.mul(&z_im);
+        let re_im = z_re.mul(&z_im);
+        z_re = re_sq.sub(&im_sq).add(&c_re);
+        z_im = re_im.mul_int(2).add(&c_im);
+
+        let z = Complex::new(z_re.to_f64(), z_im.to_f64());
+        orbit.push(z);
+        if z.norm_sqr() >= bailout_sqr {
+            break;
+        }
+    }
+
+    Ok(RefOrbit { z: orbit })
+}
+
+impl RefOrbit {
+    // Iterate a pixel as a delta from the reference orbit.
+    // d0 is the starting delta (Julia sets), dc the constant delta (Mandelbrot).
+    // Iteration count matches the standard calculation.
+    // Precision loss (glitches) is detected when the pixel point gets
+    // smaller than its delta, and when the reference orbit runs out;
+    // in both cases the delta is rebased onto the start of the orbit.
+    // The derivative of the pixel point, for distance estimation, is tracked if
+    // given its start value and the constant added each iteration.
+    // Each orbit point before divergence is passed to visit, for orbit traps.
+    pub fn iterate_delta(&self, d0: Complex<f64>, dc: Complex<f64>, max_its: u32, bailout_sqr: f64,
+        deriv: Option<(Complex<f64>, Complex<f64>)>, mut visit: impl FnMut(Complex<f64>)) -> DeltaResult {
+        let ref_end = self.z.len() - 1;
+        let mut delta = d0;
+        let mut ref_idx: usize = 0;
+        let mut px_fn = self.z[0] + delta;
+        let mut rebased = false;
+        let (mut dz, dz_c) = deriv.unwrap_or_default();
+
+        // Initialise number of iterations.
+        let mut num_its: u32 = 1;
+
+        while num_its < max_its {
+            // Derivative dz = 2 (Zn + dn) dz + dz_c, of the pixel point.
+            if deriv.is_some() {
+                dz = (2.0 * px_fn * dz) + dz_c;
+            }
+
+            // Delta function dn+1 = 2 Zn dn + dn^2 + dc.
+            delta = (2.0 * self.z[ref_idx] * delta) + (delta * delta) + dc;
+            ref_idx += 1;
+            px_fn = self.z[ref_idx] + delta;
+
+            // Check if function diverges.
+            if px_fn.norm_sqr() >= bailout_sqr {
+                break;
+            }
+            num_its += 1;
+            visit(px_fn);
+
+            // Rebase on glitch or end of reference orbit.
+            if px_fn.norm_sqr() < delta.norm_sqr() || ref_idx == ref_end {
+                delta = px_fn - self.z[0];
+                ref_idx = 0;
+                rebased = true;
+            }
+        }
+
+        DeltaResult { num_its, px_fn, dz, rebased }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fractal::{test_fractal, Fractal};
+
+    // Fractal settings for a view, with or without deep zoom.
+    fn view_fractal(view: &str, deep_zoom: bool) -> Fractal {
+        let mut fractals = test_fractal(&format!("{}\ndeep_zoom = {}", view, deep_zoom));
+        if deep_zoom {
+            fractals.cal_ref_orbit().expect("Reference orbit failed");
+        }
+        fractals
+    }
+
+    // Escape counts of every pixel by perturbation, checked against the
+    // standard calculation, returning the number of rebased pixels.
+    fn check_counts(view: &str) -> usize {
+        let standard = view_fractal(view, false);
+        let perturbed = view_fractal(view, true);
+        let mut rebased = 0;
+        for row in 0..standard.rows {
+            for col in 0..standard.cols {
+                let pt = standard.pt_lt + Complex::new(col as f64, -(row as f64)) * standard.pt_div;
+                let expected = standard.cal_point_divergence(row, col, pt);
+                let result = perturbed.cal_point_divergence(row, col, pt);
+                assert_eq!(result.its, expected.its, "row {} col {}", row, col);
+                rebased += result.rebased as usize;
+            }
+        }
+        rebased
+    }
+
+    #[test]
+    fn matches_standard_calculation() {
+        // Seahorse valley, with iterations limited so that rounding
+        // differences near the boundary don't grow into different counts.
+        check_counts(r#"
+            rows = 60
+            cols = 80
+            mid_pt = ["-0.7453", "0.1127"]
+            pt_div = "0.000001"
+            max_its = 300
+            col_palete = []
+        "#);
+    }
+
+    #[test]
+    fn matches_standard_calculation_rebased() {
+        // Centre point escapes after a few iterations, but
+        // the view reaches into the set, so pixels must rebase.
+        let view = r#"
+            rows = 40
+            cols = 60
+            mid_pt = ["0.5", "0.0"]
+            pt_div = "0.01"
+            max_its = 200
+            col_palete = []
+        "#;
+        let orbit_len = view_fractal(view, true).ref_orbit.map(|orbit| orbit.z.len()).unwrap_or_default();
+        assert!(orbit_len < 10, "Reference orbit length {}", orbit_len);
+        assert!(check_counts(view) > 0);
+    }
+}
@@ -0,0 +1,188 @@
+// Fractal formulas and the formula abstraction.
+
+use num_complex::Complex;
+use serde::{Deserialize, Deserializer, Serialize};
+use std::fmt;
+
+// Trait for the iterated function of a fractal.
+// Called for every iteration of every pixel, so implementations
+// should be cheap to call and hold no state.
+pub trait Formula: Sync {
+    // Next point in the orbit, Fn+1 = f(Fn, c).
+    fn iterate(&self, z: Complex<f64>, c: Complex<f64>) -> Complex<f64>;
+
+    // Degree of the function, used for fractional divergence.
+    fn degree(&self) -> f64;
+
+    // Derivative along the orbit, f'(Fn) dFn, for distance estimation.
+    // Defaults to that of Fn^degree, which has the right magnitude
+    // for the formulas that aren't complex differentiable.
+    fn derivative(&self, z: Complex<f64>, dz: Complex<f64>) -> Complex<f64> {
+        self.degree() * z.powf(self.degree() - 1.0) * dz
+    }
+
+    // Period of the orbit if the constant is known to be inside the set
+    // without iterating, None if not known.
+    // Only applies where iteration starts at 0, i.e. not Julia sets.
+    fn known_interior_period(&self, _c: Complex<f64>) -> Option<u32> {
+        None
+    }
+}
+
+// Mandelbrot, Fn+1 = Fn^2 + c.
+pub struct Mandelbrot;
+
+impl Formula for Mandelbrot {
+    fn iterate(&self, z: Complex<f64>, c: Complex<f64>) -> Complex<f64> {
+        (z * z) + c
+    }
+
+    fn degree(&self) -> f64 {
+        2.0
+    }
+
+    fn derivative(&self, z: Complex<f64>, dz: Complex<f64>) -> Complex<f64> {
+        2.0 * z * dz
+    }
+
+    // Main cardioid (period 1) and period-2 bulb tests.
+    fn known_interior_period(&self, c: Complex<f64>) -> Option<u32> {
+        let x_q = c.re - 0.25;
+        let y_sq = c.im * c.im;
+        let q = (x_q * x_q) + y_sq;
+        if q * (q + x_q) <= 0.25 * y_sq {
+            Some(1)
+        } else if ((c.re + 1.0) * (c.re + 1.0)) + y_sq <= 0.0625 {
+            Some(2)
+        } else {
+            None
+        }
+    }
+}
+
+// Burning Ship, Fn+1 = (|Re(Fn)| + i|Im(Fn)|)^2 + c.
+pub struct BurningShip;
+
+impl Formula for BurningShip {
+    fn iterate(&self, z: Complex<f64>, c: Complex<f64>) -> Complex<f64> {
+        let z_abs = Complex::new(z.re.abs(), z.im.abs());
+        (z_abs * z_abs) + c
+    }
+
+    fn degree(&self) -> f64 {
+        2.0
+    }
+}
+
+// Tricorn (Mandelbar), Fn+1 = conj(Fn)^2 + c.
+pub struct Tricorn;
+
+impl Formula for Tricorn {
+    fn iterate(&self, z: Complex<f64>, c: Complex<f64>) -> Complex<f64> {
+        let z_conj = z.conj();
+        (z_conj * z_conj) + c
+    }
+
+    fn degree(&self) -> f64 {
+        2.0
+    }
+}
+
+// Multibrot, Fn+1 = Fn^d + c.
+pub struct Multibrot {
+    pub power: u32,
+}
+
+impl Formula for Multibrot {
+    fn iterate(&self, z: Complex<f64>, c: Complex<f64>) -> Complex<f64> {
+        z.powu(self.power) + c
+    }
+
+    fn degree(&self) -> f64 {
+        self.power as f64
+    }
+
+    fn derivative(&self, z: Complex<f64>, dz: Complex<f64>) -> Complex<f64> {
+        self.power as f64 * z.powu(self.power - 1) * dz
+    }
+}
+
+// Range of Multibrot exponents.
+// Below 2 there is no divergence to smooth, above the
+// maximum the orbit overflows f64 before it can diverge.
+pub const MIN_POWER: u32 = 2;
+pub const MAX_POWER: u32 = 32;
+
+// Selection of built in formula.
+// This is what is saved to file under the formula key.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Default)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum FormulaType {
+    #[default]
+    Mandelbrot,
+    BurningShip,
+    Tricorn,
+    Multibrot {
+        #[serde(deserialize_with = "deserialize_power")]
+        power: u32,
+    },
+}
+
+// Multibrot exponent from file, checked to be in range.
+fn deserialize_power<'de, D: Deserializer<'de>>(deserializer: D) -> Result<u32, D::Error> {
+    let power = u32::deserialize(deserializer)?;
+    if (MIN_POWER..=MAX_POWER).contains(&power) {
+        Ok(power)
+    } else {
+        Err(serde::de::Error::custom(format!("Multibrot exponent {} not from {} to {}", power, MIN_POWER, MAX_POWER)))
+    }
+}
+
+impl FormulaType {
+    // Formula selection from the menu number and Multibrot power.
+    // Returns None if the menu number isn't a formula, or the power is out of range.
+    pub fn from_menu(choice: u32, power: u32) -> Option<FormulaType> {
+        match choice {
+            1 => Some(FormulaType::Mandelbrot),
+            2 => Some(FormulaType::BurningShip),
+            3 => Some(FormulaType::Tricorn),
+            4 if (MIN_POWER..=MAX_POWER).contains(&power) => Some(FormulaType::Multibrot { power }),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for FormulaType {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            FormulaType::Mandelbrot => write!(f, "Mandelbrot"),
+            FormulaType::BurningShip => write!(f, "Burning Ship"),
+            FormulaType::Tricorn => write!(f, "Tricorn"),
+            FormulaType::Multibrot { power } => write!(f, "Multibrot (z^{} + c)", power),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Deserialize)]
+    struct Config {
+        formula: FormulaType,
+    }
+
+    // Multibrot exponents are range checked from the menu and from file.
+    #[test]
+    fn multibrot_power_range() {
+        assert_eq!(FormulaType::from_menu(4, 3), Some(FormulaType::Multibrot { power: 3 }));
+        assert_eq!(FormulaType::from_menu(4, 1), None);
+        assert_eq!(FormulaType::from_menu(4, MAX_POWER + 1), None);
+
+        let load = |power: u32| toml::from_str::<Config>(&format!("[formula]\ntype = \"multibrot\"\npower = {}", power));
+        assert_eq!(load(5).unwrap().formula, FormulaType::Multibrot { power: 5 });
+        assert!(load(0).is_err());
+        assert!(load(1).is_err());
+        assert!(load(1000).is_err());
+    }
+}
@@ -1,16 +1,26 @@
 // Fractals data structure and methods.
 
-use log::info;
+use log::{info, warn};
 
 use num_complex::Complex;
 use serde::{Deserialize, Serialize};
-use std::fs;
+use std::fs::{self, File};
 use std::f64::consts;
 use std::io::{self};
+use std::path::Path;
 use std::time::Duration;
 use toml;
 
+use crate::bigfixed::{BigFixed, DecimalString};
+use crate::colouring::{ColourMode, DistanceMode, InteriorMode};
+use crate::deep_zoom::{self, RefOrbit};
+use crate::formula::{BurningShip, Formula, FormulaType, Mandelbrot, Multibrot, Tricorn};
+use crate::lighting::Lighting;
+use crate::orbit_trap::{OrbitTrap, TrapTexture};
+use crate::palete::ColourSpace;
+use crate::results::{self, RESULTS_EXT};
 use crate::settings::Settings;
+use crate::supersample::SuperSample;
 
 // Struct of parameters for fractals generation.
 pub struct Fractal {
@@ -18,28 +28,141 @@ pub struct Fractal {
     pub rows: u32,
     pub cols: u32,
     pub mid_pt: Complex<f64>,
+    pub mid_pt_dec: (String, String),
     pub pt_div: f64,
+    pub pt_div_dec: String,
     pub max_its: u32,
+    pub bailout: f64,
+    pub formula: FormulaType,
+    pub julia: bool,
+    pub julia_c: Complex<f64>,
+    pub deep_zoom: bool,
+    pub ref_orbit: Option<RefOrbit>,
+    pub supersample: SuperSample,
     pub left_lim: f64,
     pub top_lim: f64,
     pub escape_its: Vec<Vec<u32>>,
+    pub escape_mu: Vec<Vec<f32>>,
+    pub escape_int: Vec<Vec<f32>>,
+    pub escape_dist: Vec<Vec<f32>>,
+    pub escape_trap: Vec<Vec<f32>>,
+    pub has_results: bool,
     pub pt_lt: Complex<f64>,
     pub col_palete: Vec<(u32, (u8, u8, u8))>,
+    pub col_interp: ColourSpace,
+    pub colour_mode: ColourMode,
+    pub interior_mode: InteriorMode,
+    pub interior_colour: Option<(u8, u8, u8)>,
+    pub distance_mode: DistanceMode,
+    pub orbit_trap: OrbitTrap,
+    pub trap_texture: Option<TrapTexture>,
+    pub lighting: Lighting,
     pub calc_duration: Duration,
+    pub calc_speedup: f64,
+    pub calc_utilisation: f64,
     pub render_duration: Duration,
 }
 
+// PNG text chunk keyword for fractal settings embedded in images.
+pub const PNG_CONFIG_KEYWORD: &str = "fractal_config";
+
+// Smallest bailout radius, the escape radius of the formulas.
+// Larger radii give more accurate fractional divergence.
+pub const MIN_BAILOUT: f64 = 2.0;
+
+// Bailout radius for settings files from before it was configurable.
+fn default_bailout() -> f64 {
+    MIN_BAILOUT
+}
+
+// Distance estimation iterates diverged orbits on to this radius (squared),
+// for up to this many extra iterations.
+const DIST_BAILOUT_SQR: f64 = 1.0e8;
+const DIST_EXTRA_ITS: u32 = 16;
+
+// Distance (pixels) for points with no distance estimate, far from the set.
+const DIST_MAX: f32 = 1.0e9;
+
+// Start of the orbit derivative, and the constant added each iteration.
+// For Julia sets the derivative is with respect to the start point,
+// otherwise with respect to the constant.
+fn deriv_start(julia: bool) -> (Complex<f64>, Complex<f64>) {
+    if julia {
+        (Complex::new(1.0, 0.0), Complex::new(0.0, 0.0))
+    } else {
+        (Complex::new(0.0, 0.0), Complex::new(1.0, 0.0))
+    }
+}
+
 // Sub-Struct of parameters for fractal setting.
 // These are the parameters saved to file.
+// Results are saved to a separate binary results file, named in results_file.
+// Centre point and point division are exact decimal strings,
+// older versions wrote f64 numbers, which can still be read.
+// Results held in the TOML file (escape_its) are from older versions,
+// and are still read but no longer written.
 #[derive(Serialize, Deserialize)]
 pub struct FractalConfig {
     pub rows: u32,
     pub cols: u32,
-    pub mid_pt: (f64, f64),
-    pub pt_div: f64,
+    pub mid_pt: (DecimalString, DecimalString),
+    pub pt_div: DecimalString,
     pub max_its: u32,
+    #[serde(default = "default_bailout")]
+    pub bailout: f64,
+    #[serde(default)]
+    pub formula: FormulaType,
+    #[serde(default)]
+    pub julia: bool,
+    #[serde(default)]
+    pub julia_c: (f64, f64),
+    #[serde(default)]
+    pub deep_zoom: bool,
+    #[serde(default)]
+    pub supersample: SuperSample,
     pub col_palete: Vec<(u32, (u8, u8, u8))>,
+    #[serde(default)]
+    pub col_interp: ColourSpace,
+    #[serde(default)]
+    pub colour_mode: ColourMode,
+    #[serde(default)]
+    pub interior_mode: InteriorMode,
+    #[serde(default)]
+    pub interior_colour: Option<(u8, u8, u8)>,
+    #[serde(default)]
+    pub distance_mode: DistanceMode,
+    #[serde(default)]
+    pub orbit_trap: OrbitTrap,
+    #[serde(default)]
+    pub lighting: Lighting,
+    #[serde(default)]
+    pub results_file: Option<String>,
+    #[serde(default, skip_serializing)]
     pub escape_its: Vec<Vec<u32>>,
+    #[serde(default, skip_serializing)]
+    pub escape_mu: Vec<Vec<f32>>,
+}
+
+// Divergence results for a single point.
+// Iterations count truncated for histograms, and the
+// fractional (smooth) divergence for rendering.
+// Flags for deep zoom rebasing, for points found to be inside
+// the set without iterating to maximum iterations, and for points
+// filled in by rectangle subdivision.
+// Interior value is the data for the interior colouring mode,
+// for points inside the set, distance is the estimated
+// distance (pixels) to the set, for points outside the set,
+// and trap is the minimum distance of the orbit to the orbit trap.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct PointResult {
+    pub its: u32,
+    pub mu: f32,
+    pub interior: f32,
+    pub dist: f32,
+    pub trap: f32,
+    pub rebased: bool,
+    pub short_cut: bool,
+    pub filled: bool,
 }
 
 // Initialise all struct variables.
@@ -49,18 +172,42 @@ impl Fractal {
         info!("Initialising Fractal struct.");
 
         Fractal {
-            settings: settings,
+            settings,
             rows: 0,
             cols: 0,
             mid_pt: Complex::new(0.0, 0.0),
+            mid_pt_dec: ("0".to_string(), "0".to_string()),
             pt_div: 0.0,
+            pt_div_dec: "0".to_string(),
             max_its: 0,
+            bailout: MIN_BAILOUT,
+            formula: FormulaType::Mandelbrot,
+            julia: false,
+            julia_c: Complex::new(0.0, 0.0),
+            deep_zoom: false,
+            ref_orbit: None,
+            supersample: SuperSample::default(),
             left_lim: 0.0,
             top_lim: 0.0,
             escape_its: Vec::new(),
+            escape_mu: Vec::new(),
+            escape_int: Vec::new(),
+            escape_dist: Vec::new(),
+            escape_trap: Vec::new(),
+            has_results: false,
             pt_lt: Complex::new(0.0, 0.0),
             col_palete: Vec::new(),
+            col_interp: ColourSpace::Srgb,
+            colour_mode: ColourMode::Linear,
+            interior_mode: InteriorMode::Solid,
+            interior_colour: None,
+            distance_mode: DistanceMode::Off,
+            orbit_trap: OrbitTrap::Off,
+            trap_texture: None,
+            lighting: Lighting::default(),
             calc_duration: Duration::new(0, 0),
+            calc_speedup: 0.0,
+            calc_utilisation: 0.0,
             render_duration: Duration::new(0, 0),
         }
     }
@@ -70,11 +217,26 @@ impl Fractal {
         FractalConfig {
             rows: self.rows,
             cols: self.cols,
-            mid_pt: (self.mid_pt.re, self.mid_pt.im),
-            pt_div: self.pt_div,
+            mid_pt: (DecimalString(self.mid_pt_dec.0.clone()), DecimalString(self.mid_pt_dec.1.clone())),
+            pt_div: DecimalString(self.pt_div_dec.clone()),
             max_its: self.max_its,
+            bailout: self.bailout,
+            formula: self.formula,
+            julia: self.julia,
+            julia_c: (self.julia_c.re, self.julia_c.im),
+            deep_zoom: self.deep_zoom,
+            supersample: self.supersample,
             col_palete: self.col_palete.clone(),
-            escape_its: self.escape_its.clone(),
+            col_interp: self.col_interp,
+            colour_mode: self.colour_mode,
+            interior_mode: self.interior_mode,
+            interior_colour: self.interior_colour,
+            distance_mode: self.distance_mode,
+            orbit_trap: self.orbit_trap.clone(),
+            lighting: self.lighting,
+            results_file: None,
+            escape_its: Vec::new(),
+            escape_mu: Vec::new(),
         }
     }
 
@@ -82,28 +244,114 @@ impl Fractal {
     pub fn from_config(&mut self, config: FractalConfig) {
         self.rows = config.rows;
         self.cols = config.cols;
-        self.mid_pt = Complex::new(config.mid_pt.0, config.mid_pt.1);
-        self.pt_div = config.pt_div;
         self.max_its = config.max_its;
+        self.bailout = config.bailout.max(MIN_BAILOUT);
+        self.formula = config.formula;
+        self.julia = config.julia;
+        self.julia_c = Complex::new(config.julia_c.0, config.julia_c.1);
+        self.deep_zoom = config.deep_zoom;
+        self.supersample = config.supersample;
+
+        // Exact decimal centre point and point division,
+        // checked to be within f64 range when read.
+        self.mid_pt = Complex::new(config.mid_pt.0.to_f64(), config.mid_pt.1.to_f64());
+        self.mid_pt_dec = (config.mid_pt.0.0, config.mid_pt.1.0);
+        self.pt_div = config.pt_div.to_f64();
+        self.pt_div_dec = config.pt_div.0;
+
         self.col_palete = config.col_palete;
+        self.col_interp = config.col_interp;
+        self.colour_mode = config.colour_mode;
+        self.interior_mode = config.interior_mode;
+        self.interior_colour = config.interior_colour;
+        self.distance_mode = config.distance_mode;
+        self.orbit_trap = config.orbit_trap;
+        self.trap_texture = None;
+        self.lighting = config.lighting;
         self.init_fractal_image(self.rows,
             self.cols,
             self.mid_pt,
             self.pt_div);
+
+        // Results from older settings files held in the TOML.
+        // Only used if they match the fractal size.
+        let size_matches = |rows: usize, cols: Option<usize>| {
+            rows == self.rows as usize && cols == Some(self.cols as usize)
+        };
+        if size_matches(config.escape_its.len(), config.escape_its.first().map(|r| r.len())) {
+            self.escape_mu = config.escape_its.iter()
+                .map(|row| row.iter().map(|its| *its as f32).collect())
+                .collect();
+            self.escape_its = config.escape_its;
+            self.has_results = true;
+        }
+        if size_matches(config.escape_mu.len(), config.escape_mu.first().map(|r| r.len())) {
+            self.escape_mu = config.escape_mu;
+        }
     }
 
     // Save FractalConfig to a TOML file.
+    // Results, if calculated, are saved to a binary results file of
+    // the same name, in the same folder, with the results extension.
     pub fn save_config(&mut self, path: &str) -> io::Result<()> {
-        let config = self.to_config();
+        let mut config = self.to_config();
+        if self.has_results {
+            let results_path = Path::new(path).with_extension(RESULTS_EXT);
+            results::save_results(&results_path, self)?;
+            config.results_file = results_path.file_name().map(|f| f.to_string_lossy().into_owned());
+        }
         let toml_str = toml::to_string(&config).expect("Failed to serialize config");
         fs::write(path, toml_str)?;
         Ok(())
     }
 
-    // Load FractalConfig from a TOML file.
-    pub fn load_config(&mut self, path: &str) -> io::Result<()> {
+    // Load FractalConfig from a TOML file, and its results if with_results.
+    // A results file that is missing or invalid doesn't stop the settings
+    // loading, the fractal is left without results to be recalculated,
+    // and a warning naming the results file is returned.
+    pub fn load_config(&mut self, path: &str, with_results: bool) -> io::Result<Option<String>> {
         let toml_str = fs::read_to_string(path)?;
-        let config: FractalConfig = toml::from_str(&toml_str).expect("Failed to deserialize config");
+        let config: FractalConfig = toml::from_str(&toml_str)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        let results_file = config.results_file.clone();
+        self.from_config(config);
+
+        // Load results from the results file, which is in the same folder.
+        if let Some(results_file) = results_file.filter(|_| with_results) {
+            let results_path = Path::new(path).with_file_name(results_file);
+            if let Err(e) = results::load_results(&results_path, self) {
+                self.has_results = false;
+                let warning = format!("Failed to load results from file {:?}: {}, recalculate divergence", results_path, e);
+                warn!("{}", warning);
+                return Ok(Some(warning));
+            }
+        }
+        Ok(None)
+    }
+
+    // FractalConfig as TOML text, without results.
+    // Used to embed the fractal settings in rendered images.
+    pub fn config_text(&self) -> String {
+        toml::to_string(&self.to_config()).expect("Failed to serialize config")
+    }
+
+    // Load FractalConfig from the text chunk of a rendered PNG image.
+    // Images only hold settings, so results need to be recalculated.
+    pub fn load_image_config(&mut self, path: &str) -> io::Result<()> {
+        let decoder = png::Decoder::new(File::open(path)?);
+        let reader = decoder.read_info()
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+
+        // Find the fractal settings text chunk.
+        let toml_str = reader.info().utf8_text.iter()
+            .find(|chunk| chunk.keyword == PNG_CONFIG_KEYWORD)
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData,
+                format!("No fractal settings in image: {:?}", path)))?
+            .get_text()
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+
+        let config: FractalConfig = toml::from_str(&toml_str)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
         self.from_config(config);
         Ok(())
     }
@@ -116,24 +364,104 @@ impl Fractal {
         self.rows = rows;
         self.cols = cols;
         self.escape_its = vec![vec![0; cols as usize]; rows as usize];
+        self.escape_mu = vec![vec![0.0; cols as usize]; rows as usize];
+        self.init_mode_grids();
+        self.has_results = false;
+        self.ref_orbit = None;
+
+        // Limits are calculated from the exact decimal centre point and
+        // point division, so they are as close as f64 allows.
+        let prec = deep_zoom::precision_bits(self.pt_div);
+        let mid_pt_r = BigFixed::parse(&self.mid_pt_dec.0, prec).unwrap_or(BigFixed::from_f64(self.mid_pt.re, prec));
+        let mid_pt_i = BigFixed::parse(&self.mid_pt_dec.1, prec).unwrap_or(BigFixed::from_f64(self.mid_pt.im, prec));
+        let pt_div = BigFixed::parse(&self.pt_div_dec, prec).unwrap_or(BigFixed::from_f64(self.pt_div, prec));
 
         // Left vertice for iterating across columns.
-        let left_offset: f64 = self.mid_pt.re - (self.cols as f64 / 2.0) * self.pt_div;
-        self.left_lim = left_offset;
+        let left_offset = pt_div.mul(&BigFixed::from_f64(self.cols as f64 / 2.0, prec));
+        self.left_lim = mid_pt_r.sub(&left_offset).to_f64();
 
         // Top vertice for iterating across rows.
-        let top_offset: f64 = self.mid_pt.im + (self.rows as f64 / 2.0) * self.pt_div;
-        self.top_lim = top_offset;
+        let top_offset = pt_div.mul(&BigFixed::from_f64(self.rows as f64 / 2.0, prec));
+        self.top_lim = mid_pt_i.add(&top_offset).to_f64();
 
         // Left top vertice.
         self.pt_lt.re = self.left_lim;
         self.pt_lt.im = self.top_lim;
     }
 
-    // Methed to calculate fractal divergence at a single point.
-    // For points that reach the iteration count caculate
-    // fractional divergence.
-    pub fn cal_row_divergence(&mut self, row: u32, st_c: Complex<f64>) {
+    // True if the deep zoom calculation applies to this fractal.
+    pub fn uses_deep_zoom(&self) -> bool {
+        self.deep_zoom && self.formula == FormulaType::Mandelbrot
+    }
+
+    // True if points are checked for being inside the set.
+    // Interior modes that need the whole orbit don't use the checks,
+    // and the orbit period mode always does, as the period is found by them.
+    pub fn uses_interior_checks(&self) -> bool {
+        self.interior_mode == InteriorMode::Period
+            || (self.settings.interior_checks && self.interior_mode.allows_short_cut())
+    }
+
+    // Method to calculate the deep zoom reference orbit at the centre point.
+    // Needs to be done before calculating rows for deep zoom.
+    pub fn cal_ref_orbit(&mut self) -> Result<(), String> {
+        let julia_c = if self.julia { Some(self.julia_c) } else { None };
+        let orbit = deep_zoom::cal_ref_orbit(
+            (&self.mid_pt_dec.0, &self.mid_pt_dec.1),
+            self.pt_div,
+            julia_c,
+            self.max_its,
+            self.bailout * self.bailout)?;
+        info!("Deep zoom reference orbit length: {}", orbit.z.len());
+        self.ref_orbit = Some(orbit);
+        Ok(())
+    }
+
+    // Method to declare the arrays for the interior values, distances
+    // and orbit trap distances, only for the modes that need them,
+    // so they take no memory otherwise. Needs calling again before
+    // calculating divergence if the modes have changed.
+    pub fn init_mode_grids(&mut self) {
+        let (rows, cols) = (self.rows as usize, self.cols as usize);
+        let grid = |needed: bool| if needed { vec![vec![0.0; cols]; rows] } else { Vec::new() };
+        self.escape_int = grid(self.needs_interior());
+        self.escape_dist = grid(self.needs_distance());
+        self.escape_trap = grid(self.orbit_trap.is_on());
+    }
+
+    // True if interior values are needed, for the interior colouring mode.
+    pub fn needs_interior(&self) -> bool {
+        self.interior_mode != InteriorMode::Solid
+    }
+
+    // True if the distance estimate is needed, for distance
+    // rendering or for lighting from distance.
+    pub fn needs_distance(&self) -> bool {
+        self.distance_mode.is_on() || self.lighting.uses_distance()
+    }
+
+    // Method to load the orbit trap image, for image orbit traps.
+    // Needs to be done before calculating rows with an image trap.
+    pub fn load_trap_texture(&mut self) -> Result<(), String> {
+        self.trap_texture = match &self.orbit_trap {
+            OrbitTrap::Image { file, .. } => Some(TrapTexture::load(file)?),
+            _ => None,
+        };
+        Ok(())
+    }
+
+    // Distance of an orbit point to the orbit trap.
+    fn trap_distance(&self, z: Complex<f64>) -> f64 {
+        self.orbit_trap.distance(z, self.trap_texture.as_ref())
+    }
+
+    // Methed to calculate fractal divergence along a single row.
+    // Results are returned rather than stored so that
+    // rows can be calculated on separate worker threads.
+    pub fn cal_row_divergence(&self, row: u32, st_c: Complex<f64>) -> Vec<PointResult> {
+        // Results for each column in the row.
+        let mut row_results: Vec<PointResult> = Vec::with_capacity(self.cols as usize);
+
         // Iterante over all the columns in the row.
         // Starting point is left of the row.
         let mut pt_row: Complex<f64> = st_c;
@@ -143,47 +471,307 @@ impl Fractal {
             if col > 0 {
                 pt_row.re += self.pt_div;
             }
+            row_results.push(self.cal_point_divergence(row, col, pt_row));
+        }
+
+        row_results
+    }
+
+    // Method to store the results for a row in the results arrays.
+    pub fn store_row_divergence(&mut self, row: u32, row_results: &[PointResult]) {
+        for (col, result) in row_results.iter().enumerate() {
+            self.store_point_divergence(row, col as u32, result);
+        }
+    }
+
+    // Method to store the results for a point in the results arrays.
+    pub fn store_point_divergence(&mut self, row: u32, col: u32, result: &PointResult) {
+        let (row, col) = (row as usize, col as usize);
+        self.escape_its[row][col] = result.its;
+        self.escape_mu[row][col] = result.mu;
+        if let Some(int_row) = self.escape_int.get_mut(row) {
+            int_row[col] = result.interior;
+        }
+        if let Some(dist_row) = self.escape_dist.get_mut(row) {
+            dist_row[col] = result.dist;
+        }
+        if let Some(trap_row) = self.escape_trap.get_mut(row) {
+            trap_row[col] = result.trap;
+        }
+    }
+
+    // Methed to calculate fractal divergence at a single point.
+    // The row and column locate the point for deep zoom,
+    // otherwise the point itself is used.
+    pub fn cal_point_divergence(&self, row: u32, col: u32, pt: Complex<f64>) -> PointResult {
+        if let (true, Some(orbit)) = (self.uses_deep_zoom(), &self.ref_orbit) {
+            return self.cal_point_deep_zoom(orbit, row as f64, col as f64);
+        }
+        self.cal_point_formula_type(pt)
+    }
+
+    // Methed to calculate fractal divergence at a sample point within a pixel,
+    // for supersampling. Offset is (rows, columns) from the pixel point.
+    pub fn cal_sample_divergence(&self, row: u32, col: u32, offset: (f64, f64)) -> PointResult {
+        let row_pos = row as f64 + offset.0;
+        let col_pos = col as f64 + offset.1;
+        if let (true, Some(orbit)) = (self.uses_deep_zoom(), &self.ref_orbit) {
+            return self.cal_point_deep_zoom(orbit, row_pos, col_pos);
+        }
+        let pt = Complex::new(self.pt_lt.re + (col_pos * self.pt_div), self.pt_lt.im - (row_pos * self.pt_div));
+        self.cal_point_formula_type(pt)
+    }
+
+    // Methed to calculate fractal divergence at a point for the selected formula.
+    fn cal_point_formula_type(&self, pt: Complex<f64>) -> PointResult {
+        match self.formula {
+            FormulaType::Mandelbrot => self.cal_point_formula(&Mandelbrot, pt),
+            FormulaType::BurningShip => self.cal_point_formula(&BurningShip, pt),
+            FormulaType::Tricorn => self.cal_point_formula(&Tricorn, pt),
+            FormulaType::Multibrot { power } => self.cal_point_formula(&Multibrot { power }, pt),
+        }
+    }
+
+    // Methed to calculate fractal divergence at a single point for a formula.
+    // Generic over the formula, so that the per iteration
+    // function call isn't dispatched dynamically.
+    // For Mandelbrot type sets the point is the constant and iteration starts at 0,
+    // for Julia sets the point is the start and the constant is julia_c.
+    fn cal_point_formula<F: Formula>(&self, formula: &F, pt: Complex<f64>) -> PointResult {
+        // Log of the formula degree for fractional divergence,
+        // and the bailout radius (squared) for the divergence test.
+        let ln_degree = formula.degree().ln();
+        let bailout_sqr = self.bailout * self.bailout;
+
+        // Interior checks, and the distance (squared) at which orbit points
+        // are treated as the same, well below the point division.
+        let interior_checks = self.uses_interior_checks();
+        let cycle_eps = (self.pt_div * 1.0e-4).powi(2).min(1.0e-24);
+
+        // Define diverges flag and set to false.
+        let mut diverges: bool = false;
+
+        // Initialise divergence result and function constant.
+        let (mut px_fn, px_c): (Complex<f64>, Complex<f64>) = if self.julia {
+            (pt, self.julia_c)
+        } else {
+            (Complex::new(0.0, 0.0), pt)
+        };
+
+        // Derivative of the orbit with respect to the point, for distance estimation,
+        // and the constant added each iteration (dc/dc is 1, for Mandelbrot type sets).
+        let track_dist = self.needs_distance();
+        let (mut dz, dz_c) = deriv_start(self.julia);
+
+        // Closest the orbit gets to the orbit trap.
+        let track_trap = self.orbit_trap.is_on();
+        let mut trap_dist = f64::INFINITY;
+
+        // Points known to be inside the set don't need iterating.
+        // The period of the known interior is the interior value.
+        if interior_checks && !self.julia {
+            if let Some(period) = formula.known_interior_period(px_c) {
+                let mut result = self.point_result(self.max_its, px_fn, ln_degree);
+                result.short_cut = true;
+                result.interior = period as f32;
+                return result;
+            }
+        }
 
-            // Define diverges flag and set to false.
-            let mut diverges: bool = false;
+        // Initialise number of iterations.
+        let mut num_its: u32 = 1;
 
-            // Initialise divergence result to complex 0.
-            let mut px_fn: Complex<f64> = Complex::new(0.0, 0.0);
+        // Brent's cycle detection, compare against a saved point,
+        // saving a new point after doubling numbers of iterations.
+        let mut cycle_pt: Complex<f64> = px_fn;
+        let mut cycle_len: u32 = 1;
+        let mut cycle_its: u32 = 0;
+        let mut periodic: bool = false;
+        let mut period: u32 = 0;
 
-            // Initialise number of iterations.
-            let mut num_its: u32 = 1;
+        // Sum of orbit directions, for the mean orbit angle.
+        let mean_angle = self.interior_mode == InteriorMode::Angle;
+        let mut angle_sum: Complex<f64> = Complex::new(0.0, 0.0);
 
-            // Keep iterating until function diverges.
-            while !diverges && (num_its < self.max_its) {
-                // Perform Mandelbrot function Fn+1 = Fn^2 + pt_row.
-                px_fn = (px_fn * px_fn) + pt_row;
-                // Check if function diverges.
-                // Will diverge if modulus equal or greater than 2.
-                if px_fn.norm() >= 2.0 {
-                    diverges = true;
+        // Keep iterating until function diverges.
+        while !diverges && (num_its < self.max_its) {
+            // Derivative dFn+1 = f'(Fn) dFn + dc.
+            if track_dist {
+                dz = formula.derivative(px_fn, dz) + dz_c;
+            }
+
+            // Perform function Fn+1 = f(Fn, c).
+            px_fn = formula.iterate(px_fn, px_c);
+            // Check if function diverges.
+            // Will diverge if modulus equal or greater than the bailout radius.
+            if px_fn.norm_sqr() >= bailout_sqr {
+                diverges = true;
+            }
+            else {
+                num_its += 1;
+                if track_trap {
+                    trap_dist = trap_dist.min(self.trap_distance(px_fn));
                 }
-                else {
-                    num_its += 1;
+            }
+            if mean_angle && px_fn.norm_sqr() > 0.0 {
+                angle_sum += px_fn / px_fn.norm();
+            }
+
+            // Check if the orbit has settled into a cycle,
+            // in which case it will never diverge.
+            if interior_checks && !diverges {
+                if (px_fn - cycle_pt).norm_sqr() < cycle_eps {
+                    periodic = true;
+                    period = cycle_its + 1;
+                    break;
                 }
+                cycle_its += 1;
+                if cycle_its == cycle_len {
+                    cycle_pt = px_fn;
+                    cycle_len *= 2;
+                    cycle_its = 0;
+                }
+            }
+        }
+        if periodic {
+            num_its = self.max_its;
+        }
+
+        // Number of iterations, and fractional divergence, for point.
+        let mut result = self.point_result(num_its, px_fn, ln_degree);
+        result.short_cut = periodic;
+        result.interior = match self.interior_mode {
+            InteriorMode::Solid => 0.0,
+            InteriorMode::Magnitude => px_fn.norm() as f32,
+            InteriorMode::Period => period as f32,
+            InteriorMode::Angle => angle_sum.arg() as f32,
+        };
+        if track_dist && diverges {
+            result.dist = self.cal_distance(formula, px_fn, px_c, dz, dz_c);
+        }
+        result.trap = trap_dist as f32;
+        result
+    }
+
+    // Methed to calculate fractal divergence at a single point
+    // as a delta from the deep zoom reference orbit.
+    // Pixel positions are offsets from the centre point, so only the
+    // reference orbit needs to be calculated in high precision.
+    // Row and column are fractional for supersampling.
+    fn cal_point_deep_zoom(&self, orbit: &RefOrbit, row: f64, col: f64) -> PointResult {
+        // Mandelbrot only, so degree is 2.
+        let ln_degree = consts::LN_2;
+
+        // Offset of the point from the centre point.
+        let row_offset = (self.rows as f64 / 2.0 - row) * self.pt_div;
+        let col_offset = (col - self.cols as f64 / 2.0) * self.pt_div;
+        let pt_offset = Complex::new(col_offset, row_offset);
+
+        // Pixel offset is the starting delta for Julia sets,
+        // and the constant delta for Mandelbrot.
+        let (d0, dc) = if self.julia {
+            (pt_offset, Complex::new(0.0, 0.0))
+        } else {
+            (Complex::new(0.0, 0.0), pt_offset)
+        };
+        let deriv = if self.needs_distance() { Some(deriv_start(self.julia)) } else { None };
+        let track_trap = self.orbit_trap.is_on();
+        let mut trap_dist = f64::INFINITY;
+        let delta_result = orbit.iterate_delta(d0, dc, self.max_its, self.bailout * self.bailout, deriv, |px_fn| {
+            if track_trap {
+                trap_dist = trap_dist.min(self.trap_distance(px_fn));
             }
+        });
+
+        let mut result = self.point_result(delta_result.num_its, delta_result.px_fn, ln_degree);
+        result.rebased = delta_result.rebased;
+
+        // Only the final magnitude is available for interior colouring.
+        result.interior = match self.interior_mode {
+            InteriorMode::Magnitude => delta_result.px_fn.norm() as f32,
+            _ => f32::NAN,
+        };
+        if deriv.is_some() && delta_result.num_its < self.max_its {
+            // Diverged orbits are large, so f64 is enough to carry on iterating.
+            let (_, dz_c) = deriv_start(self.julia);
+            let px_c = if self.julia { self.julia_c } else { self.mid_pt + dc };
+            result.dist = self.cal_distance(&Mandelbrot, delta_result.px_fn, px_c, delta_result.dz, dz_c);
+        }
+        result.trap = trap_dist as f32;
+        result
+    }
 
-            // Calculate fractional divergence for higher definition.
-            let mod_fn = px_fn.norm();
-            let mu_log = if mod_fn > consts::E {
-                (mod_fn.ln().ln()) / consts::LN_2
-            } else {
-                0.0
-            };
-            let mut mu = num_its as f64 + 1.0 - mu_log;
-
-            // Limit fractional divergence to maximum iterations
-            if mu > self.max_its as f64 {
-                mu = self.max_its as f64;
+    // Method to calculate the distance estimate, in pixels, from the point
+    // where the orbit diverged and its derivative.
+    // The estimate |z| ln|z| / |dz| is only accurate well past the divergence
+    // test, so the orbit is iterated on a little further first.
+    fn cal_distance<F: Formula>(&self, formula: &F, mut px_fn: Complex<f64>, px_c: Complex<f64>,
+        mut dz: Complex<f64>, dz_c: Complex<f64>) -> f32 {
+        for _ in 0..DIST_EXTRA_ITS {
+            if px_fn.norm_sqr() >= DIST_BAILOUT_SQR {
+                break;
             }
-            num_its = mu as u32;
+            dz = formula.derivative(px_fn, dz) + dz_c;
+            px_fn = formula.iterate(px_fn, px_c);
+        }
+
+        let mod_fn = px_fn.norm();
+        let mod_dz = dz.norm();
+        if mod_dz == 0.0 {
+            return DIST_MAX;
+        }
+        let dist = mod_fn * mod_fn.ln() / mod_dz / self.pt_div;
+        (dist as f32).min(DIST_MAX)
+    }
+
+    // Method to calculate the point result from the final iteration.
+    // For points that reach the iteration count caculate
+    // fractional divergence.
+    fn point_result(&self, num_its: u32, px_fn: Complex<f64>, ln_degree: f64) -> PointResult {
+        // Calculate fractional divergence for higher definition.
+        // Diverged points are between the bailout radius R and about R^degree,
+        // so the log (base degree) of ln|z| / ln R is from 0 to 1.
+        let mod_sqr = px_fn.norm_sqr();
+        let bailout_sqr = self.bailout * self.bailout;
+        let mu_log = if mod_sqr >= bailout_sqr {
+            (mod_sqr.ln() / bailout_sqr.ln()).ln() / ln_degree
+        } else {
+            0.0
+        };
+        let mut mu = num_its as f64 + 1.0 - mu_log;
 
-            // Save number of iterations for point.
-            self.escape_its[row as usize][col as usize] = num_its;
+        // Limit fractional divergence to maximum iterations
+        if mu > self.max_its as f64 {
+            mu = self.max_its as f64;
         }
+
+        PointResult { its: mu as u32, mu: mu as f32, ..Default::default() }
     }
 }
+
+// Fractal from TOML settings text, for tests.
+// Calculated serially, with interior checks and without subdivision.
+#[cfg(test)]
+pub fn test_fractal(config: &str) -> Fractal {
+    let settings = Settings {
+        program_name: "fractals".to_string(),
+        program_ver: "test".to_string(),
+        program_devs: Vec::new(),
+        program_web: String::new(),
+        fractals_folder: ".".to_string(),
+        fractal_file: "fractal_settings.toml".to_string(),
+        hist_plot_log: false,
+        num_workers: 1,
+        interior_checks: true,
+        subdivision: false,
+    };
+    let mut fractals = Fractal::init(settings);
+    fractals.from_config(toml::from_str(config).expect("Invalid test settings"));
+    fractals
+}
+
+// Temporary file path for tests, unique to the test name and process.
+#[cfg(test)]
+pub fn test_temp_path(name: &str, ext: &str) -> std::path::PathBuf {
+    std::env::temp_dir().join(format!("fractals_{}_{}.{}", name, std::process::id(), ext))
+}
@@ -0,0 +1,177 @@
+// Lighting for an embossed, 3D look to rendered images.
+// The smooth divergence (or distance estimate) of each pixel is treated
+// as a height field, surface normals are found from the slope to the
+// neighbouring pixels, and the palete colour is shaded by a directional
+// light with ambient, diffuse and specular (Blinn-Phong) terms.
+// Heights are log scaled, as divergence climbs steeply near the set.
+// Points inside the set are flat, so are left unlit.
+
+use image::{Rgb, RgbImage};
+use serde::{Deserialize, Serialize};
+use std::fmt;
+
+use crate::fractal::Fractal;
+
+// Source of the height field.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum HeightSource {
+    // Smooth escape value, rising towards the set.
+    #[default]
+    Divergence,
+    // Distance estimate, rising towards the set.
+    Distance,
+}
+
+impl HeightSource {
+    // Height source from the menu number.
+    // Returns None if the menu number isn't a height source.
+    pub fn from_menu(choice: u32) -> Option<HeightSource> {
+        match choice {
+            1 => Some(HeightSource::Divergence),
+            2 => Some(HeightSource::Distance),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for HeightSource {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            HeightSource::Divergence => write!(f, "divergence"),
+            HeightSource::Distance => write!(f, "distance"),
+        }
+    }
+}
+
+// Lighting parameters.
+// Light azimuth is degrees anticlockwise from the right of the image,
+// and elevation is degrees up from the image.
+// Relief scales the heights, for steeper or shallower slopes.
+// This is what is saved to file under the lighting key.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(default)]
+pub struct Lighting {
+    pub enabled: bool,
+    pub height: HeightSource,
+    pub azimuth: f32,
+    pub elevation: f32,
+    pub relief: f32,
+    pub ambient: f32,
+    pub diffuse: f32,
+    pub specular: f32,
+    pub shininess: f32,
+}
+
+impl Default for Lighting {
+    fn default() -> Self {
+        Lighting {
+            enabled: false,
+            height: HeightSource::Divergence,
+            azimuth: 135.0,
+            elevation: 45.0,
+            relief: 10.0,
+            ambient: 0.3,
+            diffuse: 0.7,
+            specular: 0.3,
+            shininess: 20.0,
+        }
+    }
+}
+
+impl Lighting {
+    // True if lighting needs the distance estimate calculated.
+    pub fn uses_distance(&self) -> bool {
+        self.enabled && self.height == HeightSource::Distance
+    }
+
+    // Unit vector towards the light, (right, up, out of the image).
+    fn light_dir(&self) -> [f32; 3] {
+        let (sin_az, cos_az) = self.azimuth.to_radians().sin_cos();
+        let (sin_el, cos_el) = self.elevation.to_radians().sin_cos();
+        [cos_el * cos_az, cos_el * sin_az, sin_el]
+    }
+}
+
+impl fmt::Display for Lighting {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if !self.enabled {
+            write!(f, "off")
+        } else {
+            write!(f, "{} height (relief {}), light at {}/{} degrees, ambient {}, diffuse {}, specular {} (shininess {})",
+                self.height, self.relief, self.azimuth, self.elevation,
+                self.ambient, self.diffuse, self.specular, self.shininess)
+        }
+    }
+}
+
+// Height of each pixel, row by row, None inside the set.
+fn height_field(fractals: &Fractal) -> Vec<Vec<Option<f32>>> {
+    let max_its = fractals.max_its as f32;
+    let relief = fractals.lighting.relief;
+    fractals.escape_mu.iter().enumerate().map(|(row, mu_row)| {
+        mu_row.iter().enumerate().map(|(col, mu)| {
+            if *mu >= max_its {
+                return None;
+            }
+            Some(match fractals.lighting.height {
+                HeightSource::Divergence => relief * mu.max(0.0).ln_1p(),
+                HeightSource::Distance => {
+                    // Flat if distances weren't calculated.
+                    let dist = fractals.escape_dist.get(row).map_or(0.0, |dist_row| dist_row[col]);
+                    -relief * dist.max(0.0).ln_1p()
+                }
+            })
+        }).collect()
+    }).collect()
+}
+
+// Shade the rendered image with the lighting.
+// The image must be the fractal size.
+pub fn light_image(fractals: &Fractal, img: &mut RgbImage) {
+    let lighting = &fractals.lighting;
+    let heights = height_field(fractals);
+    let light = lighting.light_dir();
+
+    // Half way vector between the light and the viewer, for specular highlights.
+    let half = normalise([light[0], light[1], light[2] + 1.0]);
+
+    let rows = fractals.rows as usize;
+    let cols = fractals.cols as usize;
+    for row in 0..rows {
+        for col in 0..cols {
+            let Some(height) = heights[row][col] else {
+                continue;
+            };
+
+            // Slopes from the neighbouring pixels, using the pixel's own
+            // height at image edges and for neighbours inside the set.
+            let height_at = |n_row: usize, n_col: usize| heights[n_row][n_col].unwrap_or(height);
+            let left = height_at(row, col.saturating_sub(1));
+            let right = height_at(row, (col + 1).min(cols - 1));
+            let up = height_at(row.saturating_sub(1), col);
+            let down = height_at((row + 1).min(rows - 1), col);
+            let normal = normalise([(left - right) / 2.0, (down - up) / 2.0, 1.0]);
+
+            let diffuse = dot(normal, light).max(0.0);
+            let specular = dot(normal, half).max(0.0).powf(lighting.shininess);
+            let shade = lighting.ambient + lighting.diffuse * diffuse;
+            let highlight = lighting.specular * specular * 255.0;
+
+            let px_col = img.get_pixel(col as u32, row as u32).0;
+            img.put_pixel(col as u32, row as u32,
+                Rgb(px_col.map(|channel| (channel as f32 * shade + highlight).round().clamp(0.0, 255.0) as u8)));
+        }
+    }
+}
+
+// Dot product of 3D vectors.
+fn dot(a: [f32; 3], b: [f32; 3]) -> f32 {
+    a[0] * b[0] + a[1] * b[1] + a[2] * b[2]
+}
+
+// Unit vector in the direction of a 3D vector.
+fn normalise(v: [f32; 3]) -> [f32; 3] {
+    let len = dot(v, v).sqrt().max(f32::EPSILON);
+    [v[0] / len, v[1] / len, v[2] / len]
+}
@@ -1,7 +1,8 @@
 use log::info;
-use log4rs;
 
+use std::env;
 use std::path::PathBuf;
+use std::process::ExitCode;
 use std::fs::create_dir_all;
 use tokio::fs::File;
 use tokio::io::AsyncReadExt;
@@ -11,7 +12,18 @@ use crate::fractal::Fractal;
 
 pub mod settings;
 pub mod fractal;
-
+pub mod colouring;
+pub mod palete;
+pub mod formula;
+pub mod results;
+pub mod bigfixed;
+pub mod deep_zoom;
+pub mod subdivide;
+pub mod supersample;
+pub mod orbit_trap;
+pub mod lighting;
+
+mod cli;
 mod menu;
 
 // Load program settings.
@@ -24,7 +36,7 @@ async fn load_settings() -> Settings {
     settings
 }
 
-fn main() {
+fn main() -> ExitCode {
     // Logging configuration held in log4rs.yml.
     log4rs::init_file("log4rs.yml", Default::default()).unwrap();
 
@@ -42,11 +54,17 @@ fn main() {
     // Now that settings have been loaded asynchronously, run the rest of the program synchronously.
     info!("Application started: {} v({})", settings.program_name, settings.program_ver);
 
-    println!("Historian plotting logrithmic: {:?}", settings.hist_plot_log);
-
     // Create fractals class instance.
     let mut fractals: Fractal = Fractal::init(settings);
 
+    // If there is a command on the command line run it instead of the menu.
+    let args: Vec<String> = env::args().skip(1).collect();
+    if !args.is_empty() {
+        return cli::run(&args, &mut fractals);
+    }
+
+    println!("Historian plotting logrithmic: {:?}", fractals.settings.hist_plot_log);
+
     // Command line application menu.
     // Keep looping until user selects the quit option.
     loop {
@@ -85,6 +103,39 @@ fn main() {
             // Print class variables.
             "h" => menu::print_class(&mut fractals),
 
+            // Initialise new fractal from image.
+            "i" => menu::load_image_settings(&mut fractals),
+
+            // Load colour palete from palete file.
+            "j" => menu::load_palete_file(&mut fractals),
+
+            // Save colour palete to palete file.
+            "k" => menu::save_palete_file(&mut fractals),
+
+            // Select built in colour palete.
+            "l" => menu::select_builtin_palete(&mut fractals),
+
+            // Edit colour palete.
+            "m" => menu::edit_col_palete(&mut fractals),
+
+            // Generate colour palete from iterations histogram.
+            "n" => menu::auto_col_palete(&mut fractals),
+
+            // Set interior colour and interior colouring mode.
+            "o" => menu::set_interior(&mut fractals),
+
+            // Set distance rendering mode.
+            "p" => menu::set_distance(&mut fractals),
+
+            // Set orbit trap.
+            "r" => menu::set_orbit_trap(&mut fractals),
+
+            // Set lighting.
+            "s" => menu::set_lighting(&mut fractals),
+
+            // Set palete interpolation and colouring mode.
+            "t" => menu::set_colouring(&mut fractals),
+
             // Quitting application.
             "q" => {
                 println!("Quitting...");
@@ -95,4 +146,6 @@ fn main() {
             _ => println!("Invalid option."),
         }
     }
+
+    ExitCode::SUCCESS
 }
@@ -6,11 +6,22 @@ use image::{Rgb, RgbImage};
 use inline_colorization::*;
 use num_complex::Complex;
 use plotters::prelude::*;
-use std::io::{self, Write};
+use rayon::prelude::*;
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
 use std::str::FromStr;
-use std::time::{Instant};
-
-use crate::fractal::Fractal;
+use std::time::{Duration, Instant};
+
+use crate::bigfixed::DecimalString;
+use crate::colouring::{ColourMap, ColourMode, DistanceMode, InteriorMode};
+use crate::formula::{self, FormulaType};
+use crate::fractal::{Fractal, PointResult, MIN_BAILOUT, PNG_CONFIG_KEYWORD};
+use crate::lighting::{self, HeightSource, Lighting};
+use crate::orbit_trap::OrbitTrap;
+use crate::palete::{self, ColourSpace};
+use crate::subdivide;
+use crate::supersample::{self, SuperSample};
 
 // Print the menu prompt / selections.
 pub fn print_menu() {
@@ -25,6 +36,17 @@ pub fn print_menu() {
 
     println!("G) Save fractal settings & results to file");
     println!("H) Print class variables");
+    println!("I) Initialise fractal from image");
+    println!("J) Load colour palete from file");
+    println!("K) Save colour palete to file");
+    println!("L) Select built-in colour palete");
+    println!("M) Edit colour palete");
+    println!("N) Auto colour palete from iterations histogram");
+    println!("O) Set interior colouring");
+    println!("P) Set distance rendering");
+    println!("R) Set orbit trap");
+    println!("S) Set lighting");
+    println!("T) Set colouring mode");
 
     println!("{color_red}{style_bold}\nQ) Quit\n{style_reset}{color_reset}");
 }
@@ -62,6 +84,33 @@ where
     }
 }
 
+// Get the user input for a decimal number, as a string.
+// Kept as a string so that no precision is lost for deep zoom.
+// Must also be within f64 range, for standard calculation.
+pub fn get_user_input_decimal(prompt: &str) -> DecimalString {
+    loop {
+        let input = get_user_input(prompt);
+
+        // Check the input is a valid decimal number.
+        match DecimalString::parse(&input) {
+            Some(value) => return value,
+            None => println!("Invalid input. Please enter a valid value."),
+        }
+    }
+}
+
+// Get the user input for a colour.
+// Either hex "#ff8800", or red, green, blue components "255 136 0".
+pub fn get_user_input_colour(prompt: &str) -> (u8, u8, u8) {
+    loop {
+        let input = get_user_input(prompt);
+        match palete::parse_colour(&input) {
+            Some(colour) => return colour,
+            None => println!("Invalid input. Please enter a colour as #rrggbb or R G B."),
+        }
+    }
+}
+
 // User selected option to initialise new fractal.
 // Does type checking.
 pub fn enter_fractal(fractals : &mut Fractal) {
@@ -69,21 +118,119 @@ pub fn enter_fractal(fractals : &mut Fractal) {
 
     let rows: u32 = get_user_input_numeric("Number of rows: ");
     let cols: u32 = get_user_input_numeric("Number of columns: ");
-    let mid_pt_r: f64  = get_user_input_numeric("Midpoint Real axis: ");
-    let mid_pt_i: f64 = get_user_input_numeric("Midpoint Imaginary axis: ");
-    let pt_div: f64 = get_user_input_numeric("Point division: ");
+    let mid_pt_r: DecimalString = get_user_input_decimal("Midpoint Real axis: ");
+    let mid_pt_i: DecimalString = get_user_input_decimal("Midpoint Imaginary axis: ");
+    let pt_div: DecimalString = loop {
+        let pt_div = get_user_input_decimal("Point division: ");
+        if pt_div.to_f64() != 0.0 {
+            break pt_div;
+        }
+        println!("Invalid input. Please enter a valid value.");
+    };
     let max_its: u32 = get_user_input_numeric("Max iterations: ");
-    fractals.mid_pt = Complex::new(mid_pt_r, mid_pt_i);
+
+    // Bailout radius, larger radii give smoother fractional divergence.
+    let bailout: f64 = loop {
+        let input = get_user_input(&format!("Bailout radius ({} or more) [default: {}]: ", MIN_BAILOUT, MIN_BAILOUT));
+        if input.trim().is_empty() {
+            break MIN_BAILOUT;
+        }
+        match input.trim().parse::<f64>() {
+            Ok(bailout) if bailout >= MIN_BAILOUT => break bailout,
+            _ => println!("Invalid input. Please enter a valid value."),
+        }
+    };
+
+    // Fractal formula, with exponent if Multibrot.
+    println!("Formulas: 1) Mandelbrot, 2) Burning Ship, 3) Tricorn, 4) Multibrot");
+    let formula: FormulaType = loop {
+        let choice: u32 = get_user_input_numeric("Formula: ");
+        let power: u32 = if choice == 4 {
+            get_user_input_numeric(&format!("Multibrot exponent (z^d + c, {} to {}): ", formula::MIN_POWER, formula::MAX_POWER))
+        } else {
+            2
+        };
+        match FormulaType::from_menu(choice, power) {
+            Some(formula) => break formula,
+            None => println!("Invalid input. Please enter a valid value."),
+        }
+    };
+    fractals.formula = formula;
+
+    // Julia set option, if so need the function constant.
+    let julia: bool = get_user_input("Julia set (y/n) [default: n]: ").trim() == "y";
+    if julia {
+        let julia_c_r: f64 = get_user_input_numeric("Julia constant Real axis: ");
+        let julia_c_i: f64 = get_user_input_numeric("Julia constant Imaginary axis: ");
+        fractals.julia_c = Complex::new(julia_c_r, julia_c_i);
+    }
+    fractals.julia = julia;
+
+    // Deep zoom option, for point divisions too small for standard calculation.
+    let mut deep_zoom: bool = get_user_input("Deep zoom (y/n) [default: n]: ").trim() == "y";
+    if deep_zoom && formula != FormulaType::Mandelbrot {
+        println!("Deep zoom is only available for the Mandelbrot formula.");
+        deep_zoom = false;
+    }
+    fractals.deep_zoom = deep_zoom;
+
+    // Supersampling for anti-aliased rendering, 1 for none.
+    let factor: u32 = loop {
+        let prompt = format!("Supersampling factor (1 to {}, 1 for none): ", supersample::MAX_FACTOR);
+        let factor: u32 = get_user_input_numeric(&prompt);
+        if (1..=supersample::MAX_FACTOR).contains(&factor) {
+            break factor;
+        }
+        println!("Invalid input. Please enter a valid value.");
+    };
+    let jitter: bool = factor > 1 && get_user_input("Jittered samples (y/n) [default: n]: ").trim() == "y";
+
+    // Adaptive supersampling only re-samples high contrast pixels.
+    let adaptive: bool = factor > 1 && get_user_input("Adaptive, high contrast pixels only (y/n) [default: n]: ").trim() == "y";
+    let threshold: u32 = if adaptive {
+        get_user_input_numeric("Contrast threshold (iterations difference): ")
+    } else {
+        0
+    };
+    fractals.supersample = SuperSample { factor, jitter, adaptive, threshold };
+
+    // Decimal values already checked to be within f64 range.
+    fractals.mid_pt = Complex::new(mid_pt_r.to_f64(), mid_pt_i.to_f64());
+    fractals.mid_pt_dec = (mid_pt_r.0, mid_pt_i.0);
     fractals.max_its = max_its;
-    fractals.pt_div = pt_div;
+    fractals.bailout = bailout;
+    fractals.pt_div = pt_div.to_f64();
+    fractals.pt_div_dec = pt_div.0;
     fractals.init_fractal_image(rows,
                                 cols,
                                 fractals.mid_pt,
                                 fractals.pt_div);
     info!("Fractal rows: {}, cols: {}", fractals.rows, fractals.cols);
-    info!("Fractal centrepoint: {}", fractals.mid_pt);
+    info!("Fractal centrepoint: ({}, {})", fractals.mid_pt_dec.0, fractals.mid_pt_dec.1);
     info!("Fractal point division: {}", fractals.pt_div);
     info!("Fractal max iterations: {}", fractals.max_its);
+    info!("Fractal bailout radius: {}", fractals.bailout);
+    info!("Fractal formula: {}", fractals.formula);
+    if fractals.julia {
+        info!("Fractal Julia constant: {}", fractals.julia_c);
+    }
+    info!("Fractal deep zoom: {}", fractals.deep_zoom);
+    info!("Fractal supersampling: {}", fractals.supersample);
+
+    set_default_palete(fractals);
+}
+
+// Function to start with the default built in palete if none defined yet.
+pub fn set_default_palete(fractals : &mut Fractal) {
+    if fractals.col_palete.is_empty() {
+        let default_name = palete::BUILTIN_PALETES[0].name;
+        if let Some((col_palete, col_interp)) = palete::builtin_palete(default_name, fractals.max_its) {
+            fractals.col_palete = col_palete;
+            fractals.col_interp = col_interp;
+            info!("Colour palete set to built-in palete: {}", default_name);
+            println!("Colour palete set to built-in palete: {}", default_name);
+        }
+    }
 }
 
 // User selected option to initialise new fractal.
@@ -118,15 +265,47 @@ pub fn load_settings(fractals : &mut Fractal) {
     let file_path = format!("{}/{}", fractals.settings.fractals_folder, file_name);
 
     // Now call load_config without conflicting borrows.
-    let _load_status = fractals.load_config(&file_path);   
+    let _load_status = fractals.load_config(&file_path, true);   
     match _load_status {
-        Ok(_load_status) => println!("Settings loaded from: {}", file_path),
+        Ok(_load_status) => {
+            println!("Settings loaded from: {}", file_path);
+            if let Some(warning) = _load_status {
+                println!("{}", warning);
+            }
+        }
         Err(_) => println!("Failed to read from file: {:?}", file_path),
     }
 
     info!("Initialising new fractal from {:?}", file_path);
 }
 
+// User selected option to initialise new fractal.
+// As for function load_settings except settings read from a
+// previously rendered PNG image.
+pub fn load_image_settings(fractals : &mut Fractal) {
+    info!("Initialising new fractal from image.");
+
+    print!("Enter the image filename (ext .png): ");
+    io::stdout().flush().expect("Failed to flush stdout");
+
+    // Read the user's entry.
+    let mut file_name = String::new();
+    io::stdin()
+        .read_line(&mut file_name)
+        .expect("Failed to read filename");
+    let file_name = file_name.trim();
+
+    // Construct the full file path.
+    let file_path = format!("{}/{}", fractals.settings.fractals_folder, file_name);
+
+    match fractals.load_image_config(&file_path) {
+        Ok(()) => println!("Settings loaded from: {}", file_path),
+        Err(e) => println!("Failed to read settings from image {:?}: {}", file_path, e),
+    }
+
+    info!("Initialising new fractal from {:?}", file_path);
+}
+
 // Save fractal settings to file.
 pub fn save_settings(fractals: &mut Fractal) {
     // Default filename.
@@ -165,57 +344,234 @@ pub fn save_settings(fractals: &mut Fractal) {
 }
 
 // Function to calculate divergence at all points in fractal.
-// Do it row by row, leaves posibility of using multiple works to calculate
-// divergence on more than one row at a time.
+// Do it row by row, with the rows shared out between a pool of
+// worker threads (settings num_workers, 0 for all cores).
+// Or, with settings subdivision, tile by tile filling in
+// rectangles inside the Mandelbrot set.
 pub fn cal_divergence(fractals : &mut Fractal) {
     info!("Calculating fractal divergence.");
 
     // Initialise timer for divergence caluclation.
     let calc_start = Instant::now();
 
-    // Start with the left top point.
-    let mut st_c: Complex<f64> = fractals.pt_lt;
+    // Clear any previous results, so nothing stale is left if the
+    // calculation can't start, and declare the arrays the modes need,
+    // which may have changed.
+    fractals.init_fractal_image(fractals.rows, fractals.cols, fractals.mid_pt, fractals.pt_div);
 
-    // Iterate calculation over rows.
+    // Calculate the starting point for each row up front.
+    // Start with the left top point, and deduct incremental
+    // distance for every row after the first (top) row.
+    // Done serially so start points are identical to a serial run.
+    let mut row_starts: Vec<Complex<f64>> = Vec::with_capacity(fractals.rows as usize);
+    let mut st_c: Complex<f64> = fractals.pt_lt;
     for row in 0..fractals.rows {
-        // Calculate the starting point for the row.
-        // Just need to deduct incremental distance from
-        // every row after the first (top) row.
         if row > 0 {
             st_c.im -= fractals.pt_div;
         }
+        row_starts.push(st_c);
+    }
 
-        // Calculate divergence for row.
-        fractals.cal_row_divergence(row, st_c);
+    // Deep zoom needs the reference orbit before calculating rows.
+    if fractals.uses_deep_zoom() {
+        if let Err(e) = fractals.cal_ref_orbit() {
+            println!("Failed to calculate deep zoom reference orbit: {}", e);
+            return;
+        }
+    }
+
+    // Image orbit traps need the image before calculating rows.
+    if let Err(e) = fractals.load_trap_texture() {
+        println!("Failed to load orbit trap image: {}", e);
+        return;
+    }
+
+    // Worker pool for row calculations.
+    let pool = worker_pool(fractals);
+    let num_workers = pool.current_num_threads();
+
+    // Calculate divergence for each row, or each tile, on the worker pool.
+    // Keep track of the time spent in each to work out the worker utilisation.
+    let mut busy_time = Duration::new(0, 0);
+    let mut counts = CalcCounts::default();
+    let frac: &Fractal = fractals;
+    if fractals.settings.subdivision {
+        // Real part of the point for each column, calculated as for rows.
+        let mut col_res: Vec<f64> = Vec::with_capacity(fractals.cols as usize);
+        let mut pt_re: f64 = fractals.pt_lt.re;
+        for col in 0..fractals.cols {
+            if col > 0 {
+                pt_re += fractals.pt_div;
+            }
+            col_res.push(pt_re);
+        }
+        let row_ims: Vec<f64> = row_starts.iter().map(|st_c| st_c.im).collect();
+
+        let tiles = subdivide::image_tiles(fractals.rows, fractals.cols);
+        let tile_results: Vec<(Vec<PointResult>, Duration)> = pool.install(|| {
+            tiles
+                .par_iter()
+                .map(|tile| {
+                    let tile_start = Instant::now();
+                    let results = subdivide::cal_tile(frac, *tile, &row_ims, &col_res);
+                    (results, tile_start.elapsed())
+                })
+                .collect()
+        });
+
+        // Store the tile results.
+        for (tile, (results, tile_time)) in tiles.iter().zip(tile_results.iter()) {
+            for (idx, result) in results.iter().enumerate() {
+                let row = tile.row_0 + idx as u32 / tile.cols();
+                let col = tile.col_0 + idx as u32 % tile.cols();
+                fractals.store_point_divergence(row, col, result);
+            }
+            busy_time += *tile_time;
+            counts.add(results);
+        }
+    } else {
+        let row_results: Vec<(Vec<PointResult>, Duration)> = pool.install(|| {
+            row_starts
+                .par_iter()
+                .enumerate()
+                .map(|(row, st_c)| {
+                    let row_start = Instant::now();
+                    let results = frac.cal_row_divergence(row as u32, *st_c);
+                    (results, row_start.elapsed())
+                })
+                .collect()
+        });
+
+        // Store the row results, in row order.
+        for (row, (results, row_time)) in row_results.iter().enumerate() {
+            fractals.store_row_divergence(row as u32, results);
+            busy_time += *row_time;
+            counts.add(results);
+        }
     }
+    fractals.has_results = true;
 
     // Determine delta time for divergence calculation.
     fractals.calc_duration = calc_start.elapsed();
-    info!("Divergence calculations in: {:?}", fractals.calc_duration);
-    println!("Divergence calculations in: {:?}", fractals.calc_duration);
+    // Speedup is the total time the workers were busy over the elapsed time,
+    // and utilisation is the fraction of the time the workers were busy.
+    let wall_time = fractals.calc_duration.as_secs_f64().max(f64::EPSILON);
+    fractals.calc_speedup = busy_time.as_secs_f64() / wall_time;
+    fractals.calc_utilisation = fractals.calc_speedup / num_workers as f64;
+    info!("Divergence calculations in: {:?} (speedup {:.1}x)", fractals.calc_duration, fractals.calc_speedup);
+    info!("Divergence workers: {}, utilisation: {:.0}%", num_workers, fractals.calc_utilisation * 100.0);
+    println!("Divergence calculations in: {:?} (speedup {:.1}x)", fractals.calc_duration, fractals.calc_speedup);
+    println!("Divergence workers: {}, utilisation: {:.0}%", num_workers, fractals.calc_utilisation * 100.0);
+    if fractals.uses_deep_zoom() {
+        info!("Deep zoom pixels rebased: {}", counts.rebased);
+        println!("Deep zoom pixels rebased: {}", counts.rebased);
+    }
+    if fractals.uses_interior_checks() && !fractals.uses_deep_zoom() {
+        info!("Interior pixels short-circuited: {}", counts.short_cut);
+        println!("Interior pixels short-circuited: {}", counts.short_cut);
+    }
+    if fractals.settings.subdivision {
+        info!("Subdivision pixels filled: {}", counts.filled);
+        println!("Subdivision pixels filled: {}", counts.filled);
+    }
+}
+
+// Worker thread pool for calculations.
+// Settings num_workers threads, 0 for all cores.
+fn worker_pool(fractals: &Fractal) -> rayon::ThreadPool {
+    rayon::ThreadPoolBuilder::new()
+        .num_threads(fractals.settings.num_workers)
+        .build()
+        .expect("Failed to build worker thread pool")
+}
+
+// Function to render supersampled pixels.
+// Pixel rows are shared out between the worker pool as for divergence,
+// and each re-sampled pixel is the average colour of its samples,
+// so only the samples for the pixels being worked on are held.
+// Other pixels, with adaptive supersampling, use the pixel result.
+// Samples use the interior value, distances and orbit trap distance of the pixel.
+// Returns the rows of pixel colours, or None if the samples can't be calculated.
+fn render_supersamples(fractals: &mut Fractal, colour_map: &ColourMap) -> Option<Vec<Vec<Rgb<u8>>>> {
+    info!("Calculating supersamples: {}", fractals.supersample);
+
+    // Deep zoom samples need the reference orbit,
+    // which isn't saved with results loaded from file.
+    if fractals.uses_deep_zoom() && fractals.ref_orbit.is_none() {
+        if let Err(e) = fractals.cal_ref_orbit() {
+            println!("Failed to calculate deep zoom reference orbit: {}", e);
+            return None;
+        }
+    }
+
+    let frac: &Fractal = fractals;
+    let pool = worker_pool(frac);
+    let px_rows: Vec<(Vec<Rgb<u8>>, usize)> = pool.install(|| {
+        (0..frac.rows as usize)
+            .into_par_iter()
+            .map(|row| {
+                // Pixels to re-sample, all of them unless adaptive.
+                let resample = supersample::resample_row(frac, row);
+                let px_row = resample.iter().enumerate().map(|(col, resample)| {
+                    let pt_data = point_data(frac, row, col);
+                    if !resample {
+                        return point_colour(frac, colour_map, frac.escape_mu[row][col], &pt_data);
+                    }
+                    let samples = supersample::cal_pixel_samples(frac, row as u32, col as u32);
+                    avg_px_col(samples.into_iter().map(|pt_mu| point_colour(frac, colour_map, pt_mu, &pt_data)))
+                }).collect();
+                (px_row, resample.iter().filter(|resample| **resample).count())
+            })
+            .collect()
+    });
+
+    let num_resampled: usize = px_rows.iter().map(|(_, num_resampled)| num_resampled).sum();
+    info!("Supersampling pixels re-sampled: {}", num_resampled);
+    println!("Supersampling pixels re-sampled: {}", num_resampled);
+    Some(px_rows.into_iter().map(|(px_row, _)| px_row).collect())
+}
+
+// Counts of pixels calculated without full iteration.
+// Deep zoom pixels that needed rebasing, pixels found inside the set
+// early, and pixels filled in by subdivision.
+#[derive(Default)]
+struct CalcCounts {
+    rebased: usize,
+    short_cut: usize,
+    filled: usize,
+}
+
+impl CalcCounts {
+    fn add(&mut self, results: &[PointResult]) {
+        self.rebased += results.iter().filter(|result| result.rebased).count();
+        self.short_cut += results.iter().filter(|result| result.short_cut).count();
+        self.filled += results.iter().filter(|result| result.filled).count();
+    }
 }
 
 // Function to define the colour palete to use
 // when rendering images.
 // Defined as an array of iteration boundary limits and a
 // corresponding rgb value at that boundary.
+// Replaces any existing palete.
 pub fn def_col_palete(fractals : &mut Fractal) {
     info!("Defining colour palete.");
 
     println!("Enter palete boundary details.");
-    println!("Enter iteration count, followed by RGB colour at boundary.");
+    println!("Enter iteration count, followed by colour at boundary (#rrggbb or R G B).");
     println!("End with boundary at max iterations: {:?}", fractals.max_its);
 
+    // Start a new palete.
+    fractals.col_palete.clear();
+
     // Number of index boundary.
     let mut idx: u8 = 0;
 
     // First colour boundary at 0 iterations.
     let mut its_bound: u32 = 1;
     println!("({:02}) Iteration boundary: {:?}", idx, its_bound);
-    let mut red: u8 = get_user_input_numeric("     RED colour component: ");
-    let mut green: u8 = get_user_input_numeric("     GREEN colour component: ");
-    let mut blue: u8 = get_user_input_numeric("     BLUE colour component: ");
-    add_colour_to_palete(fractals, its_bound, (red, green, blue));
+    let colour = get_user_input_colour("     Colour: ");
+    add_colour_to_palete(fractals, its_bound, colour);
 
     // Increment bountary index.
     idx += 1;
@@ -224,19 +580,24 @@ pub fn def_col_palete(fractals : &mut Fractal) {
     while its_bound < fractals.max_its {
 
         let its_bound_prompt = format!("({:02}) Iterations boundary: ", idx);
-        its_bound = get_user_input_numeric(&its_bound_prompt);
-        red = get_user_input_numeric("     RED colour component: ");
-        green = get_user_input_numeric("     GREEN colour component: ");
-        blue = get_user_input_numeric("     BLUE colour component: ");    
+        let mut next_bound: u32 = get_user_input_numeric(&its_bound_prompt);
+
+        // Boundaries must increase.
+        if next_bound <= its_bound {
+            println!("Boundary must be greater than the previous boundary: {:?}", its_bound);
+            continue;
+        }
 
         // Need to check if boundary outside max bounds.
         // If greater that bounds set to max iterations.
-        if its_bound > fractals.max_its {
-            its_bound = fractals.max_its;
-        }   
+        if next_bound > fractals.max_its {
+            next_bound = fractals.max_its;
+        }
+        its_bound = next_bound;
 
         // Add next colour boundary and colour to array.
-        add_colour_to_palete(fractals, its_bound, (red, green, blue));
+        let colour = get_user_input_colour("     Colour: ");
+        add_colour_to_palete(fractals, its_bound, colour);
 
         // Increment boundary count and loop.
         idx += 1;
@@ -249,6 +610,448 @@ pub fn add_colour_to_palete(fractals : &mut Fractal, its_bound: u32, color: (u8,
     fractals.col_palete.push((its_bound, color));
 }
 
+// Print the palete editor menu prompt / selections.
+fn print_palete_menu() {
+    println!("{color_green}{style_bold}\n=============\nPalete editor\n============={style_reset}{color_reset}");
+
+    println!("L) List palete boundaries");
+    println!("I) Insert boundary");
+    println!("D) Delete boundary");
+    println!("E) Edit boundary");
+    println!("S) Sort boundaries");
+
+    println!("{color_red}{style_bold}\nQ) Back to main menu\n{style_reset}{color_reset}");
+}
+
+// Function to list the colour palete boundaries.
+fn list_col_palete(fractals : &Fractal) {
+    if fractals.col_palete.is_empty() {
+        println!("No colour palete defined.");
+        return;
+    }
+    for (idx, (its_bound, colour)) in fractals.col_palete.iter().enumerate() {
+        println!("({:02}) Iterations boundary: {:>8}  Colour: {} {:?}", idx, its_bound, palete::hex_colour(*colour), colour);
+    }
+}
+
+// Function to edit the colour palete, as a submenu.
+// Boundaries are kept increasing for inserts and edits,
+// paletes from elsewhere can be sorted if they aren't.
+pub fn edit_col_palete(fractals : &mut Fractal) {
+    info!("Editing colour palete.");
+
+    loop {
+        print_palete_menu();
+        list_col_palete(fractals);
+        let choice = get_user_input("\nPalete option: ");
+
+        match choice.trim() {
+            // List boundaries, the list is printed with the menu anyway.
+            "l" => (),
+
+            // Insert boundary in order.
+            "i" => {
+                let its_bound: u32 = get_user_input_numeric("Iterations boundary: ");
+                if fractals.col_palete.iter().any(|(bound, _)| *bound == its_bound) {
+                    println!("There is already a boundary at: {:?}", its_bound);
+                    continue;
+                }
+                let colour = get_user_input_colour("Colour (#rrggbb or R G B): ");
+                let idx = fractals.col_palete.iter().position(|(bound, _)| *bound > its_bound)
+                    .unwrap_or(fractals.col_palete.len());
+                fractals.col_palete.insert(idx, (its_bound, colour));
+            }
+
+            // Delete boundary.
+            "d" => {
+                let idx: usize = get_user_input_numeric("Boundary number to delete: ");
+                if idx < fractals.col_palete.len() {
+                    fractals.col_palete.remove(idx);
+                } else {
+                    println!("No boundary number: {:?}", idx);
+                }
+            }
+
+            // Edit boundary, keeping the boundaries increasing.
+            "e" => {
+                let idx: usize = get_user_input_numeric("Boundary number to edit: ");
+                if idx >= fractals.col_palete.len() {
+                    println!("No boundary number: {:?}", idx);
+                    continue;
+                }
+                let its_bound: u32 = get_user_input_numeric("Iterations boundary: ");
+                let colour = get_user_input_colour("Colour (#rrggbb or R G B): ");
+                let mut col_palete = fractals.col_palete.clone();
+                col_palete[idx] = (its_bound, colour);
+                match palete::check_boundaries(&col_palete) {
+                    Ok(()) => fractals.col_palete = col_palete,
+                    Err(_) => println!("Boundary must be between the boundaries either side."),
+                }
+            }
+
+            // Sort boundaries, e.g. for paletes entered out of order.
+            "s" => fractals.col_palete.sort_by_key(|(its_bound, _)| *its_bound),
+
+            // Back to the main menu, checking the palete first.
+            "q" => {
+                if let Err(idx) = palete::check_boundaries(&fractals.col_palete) {
+                    println!("Warning, boundary ({:02}) isn't greater than the one before it.", idx);
+                }
+                match fractals.col_palete.last() {
+                    Some((its_bound, _)) if *its_bound != fractals.max_its => {
+                        println!("Warning, last boundary isn't at max iterations: {:?}", fractals.max_its);
+                    }
+                    _ => (),
+                }
+                break;
+            }
+
+            // Invalid option selected.
+            _ => println!("Invalid option."),
+        }
+    }
+}
+
+// Function to load the colour palete from a palete file.
+// Fractint .map, GIMP .ggr, or .json / .toml colour stops,
+// scaled to the fractal maximum iterations.
+pub fn load_palete_file(fractals : &mut Fractal) {
+    info!("Loading colour palete from file.");
+
+    print!("Enter the palete filename (ext .map, .ggr, .json or .toml): ");
+    io::stdout().flush().expect("Failed to flush stdout");
+
+    // Read the user's entry.
+    let mut file_name = String::new();
+    io::stdin()
+        .read_line(&mut file_name)
+        .expect("Failed to read filename");
+    let file_name = file_name.trim();
+
+    // Construct the full file path.
+    let file_path = format!("{}/{}", fractals.settings.fractals_folder, file_name);
+
+    match palete::load_palete(Path::new(&file_path), fractals.max_its) {
+        Ok((col_palete, col_interp)) => {
+            fractals.col_palete = col_palete;
+            if let Some(col_interp) = col_interp {
+                fractals.col_interp = col_interp;
+            }
+            println!("Palete loaded from: {} ({} boundaries)", file_path, fractals.col_palete.len());
+        }
+        Err(e) => println!("Failed to load palete from file {:?}: {}", file_path, e),
+    }
+}
+
+// Function to save the colour palete to a palete file.
+// Format as for load_palete_file, from the extension.
+pub fn save_palete_file(fractals : &mut Fractal) {
+    info!("Saving colour palete to file.");
+
+    print!("Enter the palete filename (ext .map, .ggr, .json or .toml): ");
+    io::stdout().flush().expect("Failed to flush stdout");
+
+    // Read the user's entry.
+    let mut file_name = String::new();
+    io::stdin()
+        .read_line(&mut file_name)
+        .expect("Failed to read filename");
+    let file_name = file_name.trim();
+
+    // Construct the full file path.
+    let file_path = format!("{}/{}", fractals.settings.fractals_folder, file_name);
+
+    match palete::save_palete(Path::new(&file_path), &fractals.col_palete, fractals.col_interp) {
+        Ok(()) => println!("Palete saved to: {}", file_path),
+        Err(e) => println!("Failed to save palete to file {:?}: {}", file_path, e),
+    }
+}
+
+// Function to select one of the built in colour paletes by name,
+// scaled to the fractal maximum iterations.
+pub fn select_builtin_palete(fractals : &mut Fractal) {
+    info!("Selecting built in colour palete.");
+
+    println!("Built-in paletes: {}", palete::builtin_palete_names());
+    let name = get_user_input("Palete name: ");
+    match palete::builtin_palete(&name, fractals.max_its) {
+        Some((col_palete, col_interp)) => {
+            fractals.col_palete = col_palete;
+            fractals.col_interp = col_interp;
+            println!("Colour palete set to built-in palete: {}", name.trim());
+            info!("Colour palete set to built in palete: {}", name.trim());
+        }
+        None => println!("No built-in palete named: {:?}", name.trim()),
+    }
+}
+
+// Function to generate the colour palete from the iterations
+// histogram, with boundaries at quantiles of the iteration counts
+// and colours from a built in palete.
+pub fn auto_col_palete(fractals : &mut Fractal) {
+    info!("Generating colour palete from iterations histogram.");
+
+    if !fractals.has_results {
+        println!("Calculate fractal divergence first.");
+        return;
+    }
+
+    println!("Built-in paletes: {}", palete::builtin_palete_names());
+    let base = loop {
+        let name = get_user_input("Base palete name: ");
+        match palete::BuiltinPalete::find(&name) {
+            Some(base) => break base,
+            None => println!("No built-in palete named: {:?}", name.trim()),
+        }
+    };
+    let num_bounds: usize = loop {
+        let num_bounds: usize = get_user_input_numeric("Number of boundaries (2 or more): ");
+        if num_bounds >= 2 {
+            break num_bounds;
+        }
+        println!("Invalid input. Please enter a valid value.");
+    };
+
+    fractals.col_palete = palete::auto_palete(&fractals.escape_its, fractals.max_its, base, num_bounds);
+    fractals.col_interp = base.interp;
+    println!("Colour palete set with {} boundaries from built-in palete: {}", fractals.col_palete.len(), base.name);
+    info!("Auto colour palete: {:?}", fractals.col_palete);
+}
+
+// Function to set the interior colour and interior colouring mode,
+// for points inside the set.
+pub fn set_interior(fractals : &mut Fractal) {
+    info!("Setting interior colouring.");
+
+    // Blank for the palete end colour, as before interior colours.
+    fractals.interior_colour = loop {
+        let input = get_user_input("Interior colour (#rrggbb or R G B) [default: palete end colour]: ");
+        if input.trim().is_empty() {
+            break None;
+        }
+        match palete::parse_colour(&input) {
+            Some(colour) => break Some(colour),
+            None => println!("Invalid input. Please enter a colour as #rrggbb or R G B."),
+        }
+    };
+    info!("Interior colour: {:?}", fractals.interior_colour);
+
+    // Interior modes other than solid are coloured from the palete.
+    println!("Interior: 1) Solid, 2) Final |z| magnitude, 3) Orbit period, 4) Mean orbit angle");
+    let interior_mode = loop {
+        let choice: u32 = get_user_input_numeric("Interior colouring mode: ");
+        match InteriorMode::from_menu(choice) {
+            Some(interior_mode) => break interior_mode,
+            None => println!("Invalid input. Please enter a valid value."),
+        }
+    };
+    if interior_mode == InteriorMode::Period && !fractals.settings.interior_checks {
+        println!("Orbit period turns on interior checks, to find the period.");
+    }
+
+    // Interior data is calculated with the divergence.
+    if fractals.has_results && interior_mode != fractals.interior_mode {
+        println!("Recalculate fractal divergence for the new interior mode.");
+    }
+    fractals.interior_mode = interior_mode;
+    info!("Interior mode: {}", fractals.interior_mode);
+}
+
+// Function to set the distance rendering mode, colouring points
+// outside the set by their estimated distance to the set.
+pub fn set_distance(fractals : &mut Fractal) {
+    info!("Setting distance rendering.");
+
+    // Outline and fade are coloured from the palete, from the end colour at the set.
+    println!("Distance: 1) Off, 2) Boundary outline, 3) Distance fade, 4) Anti-aliased edge");
+    let distance_mode = loop {
+        let choice: u32 = get_user_input_numeric("Distance rendering mode: ");
+        let size: f32 = match choice {
+            2 => get_user_input_numeric("Outline width (pixels): "),
+            3 => get_user_input_numeric("Fade scale (pixels): "),
+            _ => 0.0,
+        };
+        match DistanceMode::from_menu(choice, size) {
+            Some(distance_mode) if size >= 0.0 => break distance_mode,
+            _ => println!("Invalid input. Please enter a valid value."),
+        }
+    };
+
+    // Distances are calculated with the divergence.
+    if fractals.has_results && distance_mode.is_on() && !fractals.needs_distance() {
+        println!("Recalculate fractal divergence for distance estimates.");
+    }
+    fractals.distance_mode = distance_mode;
+    info!("Distance mode: {}", fractals.distance_mode);
+}
+
+// Function to set the orbit trap, colouring points outside the set
+// by how close their orbit gets to the trap.
+pub fn set_orbit_trap(fractals : &mut Fractal) {
+    info!("Setting orbit trap.");
+
+    println!("Orbit trap: 1) Off, 2) Point, 3) Line, 4) Cross, 5) Circle, 6) Image");
+    let orbit_trap = loop {
+        let choice: u32 = get_user_input_numeric("Orbit trap shape: ");
+        if !(1..=6).contains(&choice) {
+            println!("Invalid input. Please enter a valid value.");
+            continue;
+        }
+        if choice == 1 {
+            break OrbitTrap::Off;
+        }
+
+        // Image traps are read from the fractals folder.
+        let file = if choice == 6 {
+            let file_name = get_user_input("Trap image filename: ");
+            let file_path = format!("{}/{}", fractals.settings.fractals_folder, file_name.trim());
+            if !Path::new(&file_path).exists() {
+                println!("Trap image not found: {:?}", file_path);
+                continue;
+            }
+            file_path
+        } else {
+            String::new()
+        };
+        let centre_re: f64 = get_user_input_numeric("Trap centre (real): ");
+        let centre_im: f64 = get_user_input_numeric("Trap centre (imaginary): ");
+        let centre = (centre_re, centre_im);
+        break match choice {
+            2 => OrbitTrap::Point { centre, scale: get_user_input_numeric("Fade scale: ") },
+            3 | 4 => {
+                let angle: f64 = get_user_input_numeric("Angle (degrees): ");
+                let scale: f64 = get_user_input_numeric("Fade scale: ");
+                if choice == 3 {
+                    OrbitTrap::Line { centre, angle, scale }
+                } else {
+                    OrbitTrap::Cross { centre, angle, scale }
+                }
+            }
+            5 => {
+                let radius: f64 = get_user_input_numeric("Radius: ");
+                OrbitTrap::Circle { centre, radius, scale: get_user_input_numeric("Fade scale: ") }
+            }
+            _ => OrbitTrap::Image { file, centre, size: get_user_input_numeric("Image width: ") },
+        };
+    };
+
+    // Trap distances are calculated with the divergence.
+    if fractals.has_results && orbit_trap.is_on() && orbit_trap != fractals.orbit_trap {
+        println!("Recalculate fractal divergence for the new orbit trap.");
+    }
+    fractals.orbit_trap = orbit_trap;
+    info!("Orbit trap: {}", fractals.orbit_trap);
+}
+
+// Function to set the palete interpolation colour space and colouring mode,
+// for any palete, whether entered, built-in or loaded from file.
+pub fn set_colouring(fractals : &mut Fractal) {
+    info!("Setting colouring mode.");
+    println!("Interpolation colour space: {}", fractals.col_interp);
+    println!("Colouring mode: {}", fractals.colour_mode);
+
+    // Colour space to interpolate between boundary colours in.
+    println!("Interpolation: 1) sRGB, 2) Linear RGB, 3) HSV, 4) HSL, 5) OKLab, 6) CIELAB");
+    fractals.col_interp = loop {
+        let choice: u32 = get_user_input_numeric("Interpolation colour space: ");
+        match ColourSpace::from_menu(choice) {
+            Some(col_interp) => break col_interp,
+            None => println!("Invalid input. Please enter a valid value."),
+        }
+    };
+    info!("Palete interpolation: {}", fractals.col_interp);
+
+    // Colouring mode, how divergence is mapped onto the palete.
+    // Cyclic paletes repeat, so need the cycle period and offset.
+    println!("Colouring: 1) Linear, 2) Histogram equalised, 3) Cyclic");
+    fractals.colour_mode = loop {
+        let choice: u32 = get_user_input_numeric("Colouring mode: ");
+        let (period, offset, log_scale): (f32, f32, bool) = if choice == 3 {
+            let log_scale: bool = get_user_input("Log scale iterations (y/n) [default: n]: ").trim() == "y";
+            let period: f32 = get_user_input_numeric("Cycle period (greater than 0, iterations, or log iterations): ");
+            let offset: f32 = get_user_input_numeric("Cycle offset: ");
+            (period, offset, log_scale)
+        } else {
+            (0.0, 0.0, false)
+        };
+        match ColourMode::from_menu(choice, period, offset, log_scale) {
+            Some(colour_mode) => break colour_mode,
+            None => println!("Invalid input. Please enter a valid value."),
+        }
+    };
+    info!("Colouring mode: {}", fractals.colour_mode);
+}
+
+// Function to set the lighting, shading the image as if
+// the divergence or distance were a height.
+pub fn set_lighting(fractals : &mut Fractal) {
+    info!("Setting lighting.");
+
+    let enabled: bool = get_user_input("Enable lighting (y/n): ").trim() == "y";
+    if !enabled {
+        fractals.lighting.enabled = false;
+        info!("Lighting: {}", fractals.lighting);
+        return;
+    }
+
+    println!("Height from: 1) Divergence, 2) Distance estimate");
+    let height = loop {
+        let choice: u32 = get_user_input_numeric("Height source: ");
+        match HeightSource::from_menu(choice) {
+            Some(height) => break height,
+            None => println!("Invalid input. Please enter a valid value."),
+        }
+    };
+
+    // Blank entries keep the default.
+    let defaults = Lighting::default();
+    let get_value = |prompt: &str, default: f32| -> f32 {
+        loop {
+            let input = get_user_input(&format!("{} [default: {}]: ", prompt, default));
+            if input.trim().is_empty() {
+                return default;
+            }
+            match input.trim().parse::<f32>() {
+                Ok(value) if value >= 0.0 => return value,
+                _ => println!("Invalid input. Please enter a valid value."),
+            }
+        }
+    };
+    let lighting = Lighting {
+        enabled,
+        height,
+        azimuth: get_value("Light azimuth (degrees anticlockwise from right)", defaults.azimuth),
+        elevation: get_value("Light elevation (degrees)", defaults.elevation),
+        relief: get_value("Relief", defaults.relief),
+        ambient: get_value("Ambient", defaults.ambient),
+        diffuse: get_value("Diffuse", defaults.diffuse),
+        specular: get_value("Specular", defaults.specular),
+        shininess: get_value("Shininess", defaults.shininess),
+    };
+
+    // Distances are calculated with the divergence.
+    if fractals.has_results && lighting.uses_distance() && !fractals.needs_distance() {
+        println!("Recalculate fractal divergence for distance estimates.");
+    }
+    fractals.lighting = lighting;
+    info!("Lighting: {}", fractals.lighting);
+}
+
+// Function to set the colour palete from a built in palete name,
+// or failing that a palete file path.
+pub fn set_palete(fractals : &mut Fractal, name_or_path: &str) -> io::Result<()> {
+    let (col_palete, col_interp) = match palete::builtin_palete(name_or_path, fractals.max_its) {
+        Some((col_palete, col_interp)) => (col_palete, Some(col_interp)),
+        None => palete::load_palete(Path::new(name_or_path), fractals.max_its)?,
+    };
+    fractals.col_palete = col_palete;
+    if let Some(col_interp) = col_interp {
+        fractals.col_interp = col_interp;
+    }
+    info!("Colour palete set from: {}", name_or_path);
+    Ok(())
+}
+
 // Function to render the image according to the
 // defined colour palete.
 pub fn render_image(fractals : &mut Fractal) {
@@ -266,6 +1069,16 @@ pub fn render_image(fractals : &mut Fractal) {
 
     // Construct the full file path.
     let file_path = format!("{}/{}", fractals.settings.fractals_folder, file_name);
+
+    match save_image(fractals, &file_path) {
+        Ok(()) => println!("Image rendering in: {:?}", fractals.render_duration),
+        Err(e) => println!("Failed to save image {:?}: {}", file_path, e),
+    }
+}
+
+// Function to render the image and save it to file.
+// PNG images have the fractal settings embedded as text chunks.
+pub fn save_image(fractals : &mut Fractal, file_path: &str) -> Result<(), Box<dyn std::error::Error>> {
     info!("Saving image to file: {:?}", file_path);
 
     // Initialise timer for image renderingn.
@@ -276,51 +1089,152 @@ pub fn render_image(fractals : &mut Fractal) {
     let cols = fractals.cols;
     let mut img = RgbImage::new(cols, rows);
 
+    // Colouring mode mapping from divergence to palete.
+    let colour_map = ColourMap::new(fractals);
+
+    // With supersampling the samples are calculated now, and each
+    // pixel is the average colour of its samples.
+    let px_rows = if fractals.supersample.is_on() {
+        render_supersamples(fractals, &colour_map)
+    } else {
+        None
+    };
+
     // Iterate through rows and columuns and
     // set the pixel colour accordingly.
     for y in 0..rows {
         for x in 0..cols{
-            let pt_its: u32 = fractals.escape_its[y as usize][x as usize];
-            let px_col: Rgb<u8> = det_px_col(pt_its, &fractals.col_palete);
+            let px_col: Rgb<u8> = match &px_rows {
+                Some(px_rows) => px_rows[y as usize][x as usize],
+                None => {
+                    let pt_data = point_data(fractals, y as usize, x as usize);
+                    point_colour(fractals, &colour_map, fractals.escape_mu[y as usize][x as usize], &pt_data)
+                }
+            };
             img.put_pixel(x, y, px_col);
         }
     }
 
+    // Lighting pass over the palete colours.
+    if fractals.lighting.enabled {
+        lighting::light_image(fractals, &mut img);
+    }
+
     // Save the image.
-    let _ = img.save(file_path);
+    // Other image formats are saved without settings.
+    if file_path.to_lowercase().ends_with(".png") {
+        save_png_with_config(fractals, &img, file_path)?;
+    } else {
+        img.save(file_path)?;
+    }
 
     // Determine delta time for rendering.
     fractals.render_duration = render_start.elapsed();
     info!("Image rendering in: {:?}", fractals.render_duration);
-    println!("Image rendering in: {:?}", fractals.render_duration);
+
+    Ok(())
+}
+
+// Function to save a PNG image with the fractal settings
+// and program version in text chunks.
+fn save_png_with_config(fractals : &Fractal, img: &RgbImage, file_path: &str) -> Result<(), Box<dyn std::error::Error>> {
+    let file = File::create(file_path)?;
+    let mut encoder = png::Encoder::new(BufWriter::new(file), img.width(), img.height());
+    encoder.set_color(png::ColorType::Rgb);
+    encoder.set_depth(png::BitDepth::Eight);
+
+    // Text chunks are written with the header.
+    let software = format!("{} v{}", fractals.settings.program_name, fractals.settings.program_ver);
+    encoder.add_text_chunk("Software".to_string(), software)?;
+    encoder.add_itxt_chunk(PNG_CONFIG_KEYWORD.to_string(), fractals.config_text())?;
+
+    let mut writer = encoder.write_header()?;
+    writer.write_image_data(img.as_raw())?;
+    writer.finish()?;
+
+    Ok(())
+}
+
+// Results for a point, other than divergence, used for colouring.
+struct PointData {
+    interior: f32,
+    dist: f32,
+    trap: f32,
+}
+
+// Function to get the results for a pixel used for colouring.
+// Results not calculated for the modes have no interior value,
+// and are far from the set and the orbit trap.
+fn point_data(fractals: &Fractal, row: usize, col: usize) -> PointData {
+    PointData {
+        interior: fractals.escape_int.get(row).map_or(f32::NAN, |int_row| int_row[col]),
+        dist: fractals.escape_dist.get(row).map_or(f32::INFINITY, |dist_row| dist_row[col]),
+        trap: fractals.escape_trap.get(row).map_or(f32::INFINITY, |trap_row| trap_row[col]),
+    }
+}
+
+// Function to determine the colour of a point from its fractional divergence.
+// Points inside the set are coloured by the interior colouring mode,
+// or are the interior colour if set, otherwise they are the palete end colour.
+// Points outside the set may be coloured by orbit trap, or by distance
+// to the set, instead.
+fn point_colour(fractals: &Fractal, colour_map: &ColourMap, mu: f32, pt_data: &PointData) -> Rgb<u8> {
+    let max_its = fractals.max_its as f32;
+    let dist = pt_data.dist;
+    if mu >= max_its {
+        if let Some(pal_value) = colour_map.map_interior(pt_data.interior) {
+            return det_px_col(pal_value, &fractals.col_palete, fractals.col_interp);
+        }
+        if let Some((r, g, b)) = fractals.interior_colour {
+            return Rgb([r, g, b]);
+        }
+        return det_px_col(colour_map.map(mu), &fractals.col_palete, fractals.col_interp);
+    }
+    if let Some(pal_value) = colour_map.map_trap(pt_data.trap) {
+        return det_px_col(pal_value, &fractals.col_palete, fractals.col_interp);
+    }
+    if let Some(pal_value) = colour_map.map_distance(dist) {
+        return det_px_col(pal_value, &fractals.col_palete, fractals.col_interp);
+    }
+    let px_col = det_px_col(colour_map.map(mu), &fractals.col_palete, fractals.col_interp);
+
+    // Pixels less than a pixel from the set are partly covered by it,
+    // so are blended with the colour of the set.
+    if fractals.distance_mode == DistanceMode::AntiAlias && dist < 1.0 {
+        let set_col = fractals.interior_colour
+            .unwrap_or_else(|| det_px_col(max_its, &fractals.col_palete, fractals.col_interp).0.into());
+        let (r, g, b) = fractals.col_interp.interpolate(px_col.0.into(), set_col, 1.0 - dist.max(0.0));
+        return Rgb([r, g, b]);
+    }
+    px_col
 }
 
 // Function to determine the colour of the pixel.
-// Based on linear interpolation of colour palete.
-pub fn det_px_col(its: u32, col_pal: &Vec<(u32, (u8, u8, u8))>) -> Rgb<u8> {
+// Based on interpolation of colour palete using the
+// fractional divergence, so there is no banding between iterations.
+// Colours are interpolated in the palete interpolation colour space.
+pub fn det_px_col(its: f32, col_pal: &[(u32, (u8, u8, u8))], interp: ColourSpace) -> Rgb<u8> {
 
     // Iterate through the boundaries to find where `its` fits
     // between consecutive boundaries.
-    for i in 0..col_pal.len() - 1 {
-        let (lower_bound, lower_color) = col_pal[i];
-        let (upper_bound, upper_color) = col_pal[i + 1];
+    for bounds in col_pal.windows(2) {
+        let (lower_bound, lower_color) = bounds[0];
+        let (upper_bound, upper_color) = bounds[1];
 
-        if its > lower_bound && its <= upper_bound {
-            // Perform linear interpolation between the two colours.
-            let t = (its - lower_bound) as f32 / (upper_bound - lower_bound) as f32;
-            let r = (1.0 - t) * lower_color.0 as f32 + t * upper_color.0 as f32;
-            let g = (1.0 - t) * lower_color.1 as f32 + t * upper_color.1 as f32;
-            let b = (1.0 - t) * lower_color.2 as f32 + t * upper_color.2 as f32;
+        if its > lower_bound as f32 && its <= upper_bound as f32 {
+            // Perform interpolation between the two colours.
+            let t = (its - lower_bound as f32) / (upper_bound - lower_bound) as f32;
+            let (r, g, b) = interp.interpolate(lower_color, upper_color, t);
 
             // Return interpolated colour for the pixel.
-            return Rgb([r as u8, g as u8, b as u8]);
+            return Rgb([r, g, b]);
         }
     }
 
     // Handle the case where `its` doesn't fit into any range.
     // For simplicity, return the last colour in the palette.
     if let Some(&(last_bound, last_color)) = col_pal.last() {
-        if its > last_bound {
+        if its > last_bound as f32 {
             return Rgb([last_color.0, last_color.1, last_color.2]);
         }
     }
@@ -329,13 +1243,37 @@ pub fn det_px_col(its: u32, col_pal: &Vec<(u32, (u8, u8, u8))>) -> Rgb<u8> {
     Rgb([0, 0, 0])
 }
 
+// Function to determine the colour of a pixel from its samples.
+// Colours of the samples are averaged, rather than the divergence,
+// so that detail smaller than the pixel blends smoothly.
+pub fn avg_px_col(samples: impl Iterator<Item = Rgb<u8>>) -> Rgb<u8> {
+    let mut sum: [u32; 3] = [0, 0, 0];
+    let mut num_samples: u32 = 0;
+    for px_col in samples {
+        for (total, channel) in sum.iter_mut().zip(px_col.0) {
+            *total += channel as u32;
+        }
+        num_samples += 1;
+    }
+
+    // Round to the nearest colour.
+    let num_samples = num_samples.max(1);
+    Rgb(sum.map(|total| ((total + num_samples / 2) / num_samples) as u8))
+}
+
 // Generate iterations count histogram plot.
 // Useful tell when generating colour paletes as shows
 // iteration hot spots.
 pub fn generate_histogram(fractals : &mut Fractal) -> Result<(), Box<dyn std::error::Error>> {
-    info!("Generating iterations histogram.");
+    plot_histogram(fractals, "div_histogram.png")
+}
+
+// Generate iterations count histogram plot to the given file,
+// with counts for each iteration up to the maximum.
+pub fn plot_histogram(fractals : &mut Fractal, file_path: &str) -> Result<(), Box<dyn std::error::Error>> {
+    info!("Generating iterations histogram to file: {:?}", file_path);
 
-    let root = BitMapBackend::new("div_histogram.png", (800, 600)).into_drawing_area();
+    let root = BitMapBackend::new(file_path, (800, 600)).into_drawing_area();
     root.fill(&WHITE)?;
 
     // Initialise plot vector.
@@ -376,7 +1314,7 @@ pub fn generate_histogram(fractals : &mut Fractal) -> Result<(), Box<dyn std::er
 
         // If applicable, apply logarithmic transformation (add 1 to avoid log(0)).
         // Natural log (ln(1 + x)) for better scaling.
-        if fractals.settings.hist_plot_log == true {
+        if fractals.settings.hist_plot_log {
             // Push the iteration and count to the data array.
             // This time log of iteration count for scaling.
             let log_cnt = (its_cnt as f64).ln_1p() as u32;
@@ -389,7 +1327,7 @@ pub fn generate_histogram(fractals : &mut Fractal) -> Result<(), Box<dyn std::er
     }
 
     // Calculate the maximum logarithmic value for Y-axis.
-    if fractals.settings.hist_plot_log == true {
+    if fractals.settings.hist_plot_log {
         max_count = (max_count as f64).ln_1p() as u32;
     }
 
@@ -402,9 +1340,9 @@ pub fn generate_histogram(fractals : &mut Fractal) -> Result<(), Box<dyn std::er
     // Title for histogram plot.
     // Indicate if log scale.
     let mut plot_title: String = "Divergence Histogram".to_string();
-    if fractals.settings.hist_plot_log == true {
+    if fractals.settings.hist_plot_log {
         // plot_title = format!("{:?} - {:?}", plot_title, "Log Scale".to_string());
-        plot_title = format!("{} - {}", plot_title, "Log Scale".to_string());
+        plot_title = format!("{} - {}", plot_title, "Log Scale");
     }
 
     // Create a chart builder with dynamic Y-axis range.
@@ -419,14 +1357,14 @@ pub fn generate_histogram(fractals : &mut Fractal) -> Result<(), Box<dyn std::er
 
     chart
         .draw_series(LineSeries::new(
-            data.into_iter(),
-            &RED,
+            data,
+            RED,
         ))?
         .label(plot_title)
-        .legend(|(x, y)| PathElement::new(vec![(x, y), (x + 20, y)], &RED));
+        .legend(|(x, y)| PathElement::new(vec![(x, y), (x + 20, y)], RED));
 
     // Add a legend.
-    chart.configure_series_labels().border_style(&BLACK).draw()?;
+    chart.configure_series_labels().border_style(BLACK).draw()?;
 
     Ok(())
 }
@@ -436,10 +1374,103 @@ pub fn print_class(fractals : &mut Fractal) {
     println!("Rows           : {:?}", fractals.rows);
     println!("Columns        : {:?}", fractals.cols);
     println!("Centre point   : {:?}", fractals.mid_pt);
+    println!("Centre decimal : ({}, {})", fractals.mid_pt_dec.0, fractals.mid_pt_dec.1);
     println!("Point division : {:?}", fractals.pt_div);
     println!("Max iterations : {:?}", fractals.max_its);
+    println!("Bailout radius : {:?}", fractals.bailout);
+    println!("Formula        : {}", fractals.formula);
+    println!("Julia set      : {:?}", fractals.julia);
+    println!("Julia constant : {:?}", fractals.julia_c);
+    println!("Deep zoom      : {:?}", fractals.deep_zoom);
+    println!("Supersampling  : {}", fractals.supersample);
     println!("Left limit     : {:?}", fractals.left_lim);
     println!("Top limit      : {:?}", fractals.top_lim);
     println!("Left top point : {:?}", fractals.pt_lt);
     println!("Colour palete  : {:?}", fractals.col_palete);
+    println!("Palete interp  : {}", fractals.col_interp);
+    println!("Colouring mode : {}", fractals.colour_mode);
+    println!("Interior mode  : {}", fractals.interior_mode);
+    println!("Interior colour: {:?}", fractals.interior_colour);
+    println!("Distance mode  : {}", fractals.distance_mode);
+    println!("Orbit trap     : {}", fractals.orbit_trap);
+    println!("Lighting       : {}", fractals.lighting);
+    println!("Calc duration  : {:?}", fractals.calc_duration);
+    println!("Calc speedup   : {:.1}x", fractals.calc_speedup);
+    println!("Calc utilisation: {:.0}%", fractals.calc_utilisation * 100.0);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fractal::test_fractal;
+
+    const VIEW: &str = r#"
+        rows = 60
+        cols = 80
+        mid_pt = ["-0.6", "0"]
+        pt_div = "0.035"
+        max_its = 200
+        col_palete = []
+    "#;
+
+    // Parallel rows give the same results as a serial calculation.
+    #[test]
+    fn parallel_matches_serial() {
+        let mut serial = test_fractal(VIEW);
+        cal_divergence(&mut serial);
+
+        let mut parallel = test_fractal(VIEW);
+        parallel.settings.num_workers = 4;
+        cal_divergence(&mut parallel);
+
+        assert_eq!(serial.escape_its, parallel.escape_its);
+        assert_eq!(serial.escape_mu, parallel.escape_mu);
+    }
+
+    // Interior, distance and orbit trap arrays are only held for modes that use them.
+    #[test]
+    fn mode_grids_only_when_needed() {
+        let mut fractals = test_fractal(VIEW);
+        cal_divergence(&mut fractals);
+        assert!(fractals.escape_int.is_empty());
+        assert!(fractals.escape_dist.is_empty());
+        assert!(fractals.escape_trap.is_empty());
+
+        fractals.interior_mode = InteriorMode::Magnitude;
+        fractals.distance_mode = DistanceMode::AntiAlias;
+        fractals.orbit_trap = OrbitTrap::Point { centre: (0.0, 0.0), scale: 1.0 };
+        cal_divergence(&mut fractals);
+        for grid in [&fractals.escape_int, &fractals.escape_dist, &fractals.escape_trap] {
+            assert_eq!(grid.len(), fractals.rows as usize);
+            assert!(grid.iter().all(|row| row.len() == fractals.cols as usize));
+        }
+    }
+
+    // Orbit period interior mode finds periods without interior checks in settings.
+    #[test]
+    fn period_without_interior_checks() {
+        let mut fractals = test_fractal(VIEW);
+        fractals.settings.interior_checks = false;
+        fractals.interior_mode = InteriorMode::Period;
+        cal_divergence(&mut fractals);
+
+        // Centre row crosses the main cardioid (period 1) and the period 2 bulb.
+        let centre_row = &fractals.escape_int[fractals.rows as usize / 2];
+        assert!(centre_row.contains(&1.0));
+        assert!(centre_row.contains(&2.0));
+    }
+
+    // Failing to start the calculation leaves no results, rather than stale ones.
+    #[test]
+    fn failed_start_clears_results() {
+        let mut fractals = test_fractal(VIEW);
+        cal_divergence(&mut fractals);
+        assert!(fractals.has_results);
+
+        fractals.orbit_trap = OrbitTrap::Image { file: "missing_trap.png".to_string(), centre: (0.0, 0.0), size: 1.0 };
+        cal_divergence(&mut fractals);
+        assert!(!fractals.has_results);
+        assert!(fractals.escape_its.iter().flatten().all(|its| *its == 0));
+        assert_eq!(fractals.escape_trap.len(), fractals.rows as usize);
+    }
 }
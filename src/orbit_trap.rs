@@ -0,0 +1,142 @@
+// Orbit traps for colouring by the path of each point's orbit.
+// During iteration the minimum distance of the orbit to the trap
+// shape is recorded, and points outside the set are coloured from that
+// distance instead of the divergence, from the palete end colour at the
+// trap, fading with distance.
+// Image traps use an image as the trap, where the distance is the
+// brightness of the image where the orbit lands, so the darkest part of
+// the image the orbit passes through is recorded. Points with orbits that
+// miss the image are coloured by divergence.
+
+use image::GenericImageView;
+use num_complex::Complex;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+
+// Orbit trap shape and parameters.
+// Centre points, radius, size and scale are in the complex plane,
+// angles are in degrees anticlockwise from the real axis.
+// This is what is saved to file under the orbit_trap key.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Default)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum OrbitTrap {
+    #[default]
+    Off,
+    Point { centre: (f64, f64), scale: f64 },
+    // Line through the centre point.
+    Line { centre: (f64, f64), angle: f64, scale: f64 },
+    // Pair of perpendicular lines crossing at the centre point.
+    Cross { centre: (f64, f64), angle: f64, scale: f64 },
+    Circle { centre: (f64, f64), radius: f64, scale: f64 },
+    // Image file centred on the centre point, size wide.
+    Image { file: String, centre: (f64, f64), size: f64 },
+}
+
+impl OrbitTrap {
+    // True if orbit trap distances need to be calculated.
+    pub fn is_on(&self) -> bool {
+        *self != OrbitTrap::Off
+    }
+
+    // Distance of an orbit point to the trap.
+    // Image traps need the image texture, infinite if missing.
+    pub fn distance(&self, z: Complex<f64>, texture: Option<&TrapTexture>) -> f64 {
+        match self {
+            OrbitTrap::Off => f64::INFINITY,
+            OrbitTrap::Point { centre, .. } => (z - centre_pt(*centre)).norm(),
+            OrbitTrap::Line { centre, angle, .. } => line_distance(z - centre_pt(*centre), *angle),
+            OrbitTrap::Cross { centre, angle, .. } => {
+                let offset = z - centre_pt(*centre);
+                line_distance(offset, *angle).min(line_distance(offset, angle + 90.0))
+            }
+            OrbitTrap::Circle { centre, radius, .. } => ((z - centre_pt(*centre)).norm() - radius).abs(),
+            OrbitTrap::Image { centre, size, .. } => match texture {
+                Some(texture) => texture.brightness(z - centre_pt(*centre), *size),
+                None => f64::INFINITY,
+            },
+        }
+    }
+
+    // Level from 0 to 1 over the palete for a minimum orbit distance,
+    // 1 at the trap. Returns None if there is no trap, or the orbit missed it.
+    pub fn level(&self, dist: f32) -> Option<f32> {
+        if !dist.is_finite() {
+            return None;
+        }
+        match self {
+            OrbitTrap::Off => None,
+            OrbitTrap::Point { scale, .. }
+            | OrbitTrap::Line { scale, .. }
+            | OrbitTrap::Cross { scale, .. }
+            | OrbitTrap::Circle { scale, .. } => {
+                if *scale <= 0.0 {
+                    return None;
+                }
+                Some((-dist / *scale as f32).exp())
+            }
+            OrbitTrap::Image { .. } => Some((1.0 - dist).clamp(0.0, 1.0)),
+        }
+    }
+}
+
+impl fmt::Display for OrbitTrap {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            OrbitTrap::Off => write!(f, "Off"),
+            OrbitTrap::Point { centre, scale } => write!(f, "Point at {:?} (scale {})", centre, scale),
+            OrbitTrap::Line { centre, angle, scale } => {
+                write!(f, "Line through {:?} at {} degrees (scale {})", centre, angle, scale)
+            }
+            OrbitTrap::Cross { centre, angle, scale } => {
+                write!(f, "Cross at {:?}, {} degrees (scale {})", centre, angle, scale)
+            }
+            OrbitTrap::Circle { centre, radius, scale } => {
+                write!(f, "Circle at {:?}, radius {} (scale {})", centre, radius, scale)
+            }
+            OrbitTrap::Image { file, centre, size } => write!(f, "Image {:?} at {:?}, size {}", file, centre, size),
+        }
+    }
+}
+
+// Trap centre as a complex point.
+fn centre_pt(centre: (f64, f64)) -> Complex<f64> {
+    Complex::new(centre.0, centre.1)
+}
+
+// Distance of a point from a line through the origin at an angle (degrees).
+fn line_distance(offset: Complex<f64>, angle: f64) -> f64 {
+    let (sin, cos) = angle.to_radians().sin_cos();
+    (offset.im * cos - offset.re * sin).abs()
+}
+
+// Image for image orbit traps, as brightness from 0 (black) to 1 (white).
+pub struct TrapTexture {
+    width: u32,
+    height: u32,
+    brightness: Vec<f32>,
+}
+
+impl TrapTexture {
+    // Load image trap texture from an image file.
+    pub fn load(path: &str) -> Result<Self, String> {
+        let img = image::open(path).map_err(|e| format!("{:?}: {}", path, e))?;
+        let (width, height) = img.dimensions();
+        let brightness = img.to_luma8().pixels().map(|px| px.0[0] as f32 / 255.0).collect();
+        Ok(TrapTexture { width, height, brightness })
+    }
+
+    // Brightness of the image at an offset from its centre, for an image
+    // size wide. Infinite outside the image, so it is never the minimum.
+    fn brightness(&self, offset: Complex<f64>, size: f64) -> f64 {
+        if size <= 0.0 || self.width == 0 {
+            return f64::INFINITY;
+        }
+        let px_size = size / self.width as f64;
+        let x = offset.re / px_size + self.width as f64 / 2.0;
+        let y = self.height as f64 / 2.0 - offset.im / px_size;
+        if x < 0.0 || y < 0.0 || x >= self.width as f64 || y >= self.height as f64 {
+            return f64::INFINITY;
+        }
+        self.brightness[(y as u32 * self.width + x as u32) as usize] as f64
+    }
+}
@@ -0,0 +1,906 @@
+// Colour palete interpolation, and palete files.
+// Colours between palete boundaries are interpolated in a selectable
+// colour space. Interpolating the sRGB bytes directly gives dark, muddy
+// midpoints, linear RGB and the perceptual spaces (OKLab, CIELAB) blend
+// evenly, and HSV / HSL blend around the colour wheel.
+// Palete files are independent of a fractal, so hold colour stops at
+// positions from 0 to 1, which are scaled to iteration boundaries
+// from 1 to the fractal maximum iterations.
+
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+// Colour palete, iteration boundaries and the colour at the boundary.
+pub type ColPalete = Vec<(u32, (u8, u8, u8))>;
+
+// Number of colours in a Fractint map file.
+const MAP_COLOURS: usize = 256;
+
+// Number of steps GIMP gradient HSV segments are sampled in.
+const GGR_HSV_STEPS: usize = 8;
+
+// Built in palete, colour stops at positions from 0 to 1 as for palete files.
+// All end in black at maximum iterations, for inside the set.
+pub struct BuiltinPalete {
+    pub name: &'static str,
+    pub interp: ColourSpace,
+    pub stops: &'static [(f32, (u8, u8, u8))],
+}
+
+// Library of built in paletes, the first is the default for new fractals.
+pub const BUILTIN_PALETES: &[BuiltinPalete] = &[
+    BuiltinPalete {
+        name: "blue_gold",
+        interp: ColourSpace::LinearRgb,
+        stops: &[
+            (0.0, (0, 7, 100)),
+            (0.16, (32, 107, 203)),
+            (0.42, (237, 255, 255)),
+            (0.64, (255, 170, 0)),
+            (0.86, (0, 2, 0)),
+            (1.0, (0, 0, 0)),
+        ],
+    },
+    BuiltinPalete {
+        name: "fire",
+        interp: ColourSpace::LinearRgb,
+        stops: &[
+            (0.0, (0, 0, 0)),
+            (0.25, (128, 0, 0)),
+            (0.5, (255, 80, 0)),
+            (0.75, (255, 200, 0)),
+            (0.98, (255, 255, 200)),
+            (1.0, (0, 0, 0)),
+        ],
+    },
+    BuiltinPalete {
+        name: "grayscale",
+        interp: ColourSpace::Srgb,
+        stops: &[
+            (0.0, (0, 0, 0)),
+            (0.98, (255, 255, 255)),
+            (1.0, (0, 0, 0)),
+        ],
+    },
+    BuiltinPalete {
+        name: "rainbow",
+        interp: ColourSpace::Hsv,
+        stops: &[
+            (0.0, (255, 0, 0)),
+            (0.33, (0, 255, 0)),
+            (0.66, (0, 0, 255)),
+            (0.98, (255, 0, 255)),
+            (1.0, (0, 0, 0)),
+        ],
+    },
+    BuiltinPalete {
+        name: "viridis",
+        interp: ColourSpace::Oklab,
+        stops: &[
+            (0.0, (68, 1, 84)),
+            (0.25, (59, 82, 139)),
+            (0.5, (33, 145, 140)),
+            (0.75, (94, 201, 98)),
+            (0.98, (253, 231, 37)),
+            (1.0, (0, 0, 0)),
+        ],
+    },
+];
+
+impl BuiltinPalete {
+    // Built in palete by name, None if there isn't one of that name.
+    pub fn find(name: &str) -> Option<&'static BuiltinPalete> {
+        BUILTIN_PALETES.iter().find(|builtin| builtin.name == name.trim().to_lowercase())
+    }
+
+    // Colour stops, as for palete files.
+    pub fn palete_stops(&self) -> Vec<PaleteStop> {
+        self.stops.iter()
+            .map(|(pos, colour)| PaleteStop { pos: *pos, colour: *colour })
+            .collect()
+    }
+}
+
+// Built in palete by name, scaled to maximum iterations.
+// Returns the palete and its interpolation colour space,
+// or None if there is no built in palete of that name.
+pub fn builtin_palete(name: &str, max_its: u32) -> Option<(ColPalete, ColourSpace)> {
+    let builtin = BuiltinPalete::find(name)?;
+    Some((stops_to_palete(&builtin.palete_stops(), max_its), builtin.interp))
+}
+
+// Palete with boundaries at quantiles of the iteration counts of
+// points outside the set, so each band of colour covers about the
+// same number of pixels. Colours are taken evenly along the base
+// palete, with the base palete end colour at maximum iterations,
+// so the last band runs from the highest quantile to maximum iterations.
+// Boundaries that fall on the same iteration count are merged,
+// so there may be fewer than asked for.
+pub fn auto_palete(escape_its: &[Vec<u32>], max_its: u32, base: &BuiltinPalete, num_bounds: usize) -> ColPalete {
+    let mut its_sorted: Vec<u32> = escape_its.iter()
+        .flatten()
+        .copied()
+        .filter(|its| *its < max_its)
+        .collect();
+    its_sorted.sort_unstable();
+
+    let base_stops = base.palete_stops();
+    let end_colour = stops_colour(&base_stops, base.interp, 1.0);
+    if its_sorted.is_empty() || num_bounds < 2 {
+        return vec![(0, end_colour), (max_its, end_colour)];
+    }
+
+    let mut col_pal: ColPalete = Vec::with_capacity(num_bounds + 1);
+    let last = its_sorted.len() - 1;
+    for idx in 0..num_bounds {
+        let quantile = idx as f32 / num_bounds as f32;
+        let mut its_bound = its_sorted[(quantile * last as f32).round() as usize];
+
+        // First boundary below the lowest count, as the palete lookup excludes it.
+        if idx == 0 {
+            its_bound = its_bound.saturating_sub(1);
+        }
+        if col_pal.last().is_none_or(|(bound, _)| its_bound > *bound) {
+            let colour = stops_colour(&base_stops, base.interp, idx as f32 / num_bounds as f32);
+            col_pal.push((its_bound, colour));
+        }
+    }
+    col_pal.push((max_its, end_colour));
+    col_pal
+}
+
+// Names of the built in paletes, comma separated.
+pub fn builtin_palete_names() -> String {
+    BUILTIN_PALETES.iter().map(|builtin| builtin.name).collect::<Vec<_>>().join(", ")
+}
+
+// Selection of palete interpolation colour space.
+// This is what is saved to file under the col_interp key.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum ColourSpace {
+    #[default]
+    Srgb,
+    LinearRgb,
+    Hsv,
+    Hsl,
+    Oklab,
+    Cielab,
+}
+
+impl ColourSpace {
+    // Colour space from the menu number.
+    // Returns None if the menu number isn't a colour space.
+    pub fn from_menu(choice: u32) -> Option<ColourSpace> {
+        match choice {
+            1 => Some(ColourSpace::Srgb),
+            2 => Some(ColourSpace::LinearRgb),
+            3 => Some(ColourSpace::Hsv),
+            4 => Some(ColourSpace::Hsl),
+            5 => Some(ColourSpace::Oklab),
+            6 => Some(ColourSpace::Cielab),
+            _ => None,
+        }
+    }
+
+    // Interpolate between two colours, t from 0 (first) to 1 (second).
+    // sRGB interpolates the bytes as always done, truncating the result.
+    pub fn interpolate(&self, col_0: (u8, u8, u8), col_1: (u8, u8, u8), t: f32) -> (u8, u8, u8) {
+        let rgb_0 = to_unit(col_0);
+        let rgb_1 = to_unit(col_1);
+        let rgb = match self {
+            ColourSpace::Srgb => {
+                let lerp = |c_0: u8, c_1: u8| ((1.0 - t) * c_0 as f32 + t * c_1 as f32) as u8;
+                return (lerp(col_0.0, col_1.0), lerp(col_0.1, col_1.1), lerp(col_0.2, col_1.2));
+            }
+            ColourSpace::LinearRgb => {
+                linear_to_srgb(lerp3(srgb_to_linear(rgb_0), srgb_to_linear(rgb_1), t))
+            }
+            ColourSpace::Hsv => hsv_to_rgb(lerp_hue(rgb_to_hsv(rgb_0), rgb_to_hsv(rgb_1), t)),
+            ColourSpace::Hsl => hsl_to_rgb(lerp_hue(rgb_to_hsl(rgb_0), rgb_to_hsl(rgb_1), t)),
+            ColourSpace::Oklab => {
+                let lab = lerp3(linear_to_oklab(srgb_to_linear(rgb_0)), linear_to_oklab(srgb_to_linear(rgb_1)), t);
+                linear_to_srgb(oklab_to_linear(lab))
+            }
+            ColourSpace::Cielab => {
+                let lab = lerp3(linear_to_cielab(srgb_to_linear(rgb_0)), linear_to_cielab(srgb_to_linear(rgb_1)), t);
+                linear_to_srgb(cielab_to_linear(lab))
+            }
+        };
+        from_unit(rgb)
+    }
+}
+
+impl fmt::Display for ColourSpace {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ColourSpace::Srgb => write!(f, "sRGB"),
+            ColourSpace::LinearRgb => write!(f, "Linear RGB"),
+            ColourSpace::Hsv => write!(f, "HSV"),
+            ColourSpace::Hsl => write!(f, "HSL"),
+            ColourSpace::Oklab => write!(f, "OKLab"),
+            ColourSpace::Cielab => write!(f, "CIELAB"),
+        }
+    }
+}
+
+type Col3 = [f32; 3];
+
+// 8 bit colour to components from 0 to 1, and back with rounding.
+fn to_unit(col: (u8, u8, u8)) -> Col3 {
+    [col.0 as f32 / 255.0, col.1 as f32 / 255.0, col.2 as f32 / 255.0]
+}
+
+fn from_unit(rgb: Col3) -> (u8, u8, u8) {
+    let byte = |value: f32| (value.clamp(0.0, 1.0) * 255.0).round() as u8;
+    (byte(rgb[0]), byte(rgb[1]), byte(rgb[2]))
+}
+
+fn lerp3(a: Col3, b: Col3, t: f32) -> Col3 {
+    [
+        a[0] + t * (b[0] - a[0]),
+        a[1] + t * (b[1] - a[1]),
+        a[2] + t * (b[2] - a[2]),
+    ]
+}
+
+// sRGB gamma decoding and encoding.
+fn srgb_to_linear(rgb: Col3) -> Col3 {
+    rgb.map(|c| if c <= 0.04045 { c / 12.92 } else { ((c + 0.055) / 1.055).powf(2.4) })
+}
+
+fn linear_to_srgb(rgb: Col3) -> Col3 {
+    rgb.map(|c| {
+        let c = c.clamp(0.0, 1.0);
+        if c <= 0.003_130_8 { c * 12.92 } else { 1.055 * c.powf(1.0 / 2.4) - 0.055 }
+    })
+}
+
+// Interpolate hue, saturation, value / lightness, with hue (0 to 1)
+// going the shortest way round the colour wheel. Grey colours have
+// no hue, so take the hue of the other colour.
+fn lerp_hue(a: Col3, b: Col3, t: f32) -> Col3 {
+    let (hue_a, hue_b) = match (a[1] > 0.0, b[1] > 0.0) {
+        (false, true) => (b[0], b[0]),
+        (true, false) => (a[0], a[0]),
+        _ => (a[0], b[0]),
+    };
+    let mut d_hue = hue_b - hue_a;
+    if d_hue > 0.5 {
+        d_hue -= 1.0;
+    } else if d_hue < -0.5 {
+        d_hue += 1.0;
+    }
+    [
+        (hue_a + t * d_hue).rem_euclid(1.0),
+        a[1] + t * (b[1] - a[1]),
+        a[2] + t * (b[2] - a[2]),
+    ]
+}
+
+// Hue (0 to 1), with max and min components.
+fn rgb_hue(rgb: Col3) -> (f32, f32, f32) {
+    let max = rgb[0].max(rgb[1]).max(rgb[2]);
+    let min = rgb[0].min(rgb[1]).min(rgb[2]);
+    let delta = max - min;
+    let hue = if delta == 0.0 {
+        0.0
+    } else if max == rgb[0] {
+        ((rgb[1] - rgb[2]) / delta).rem_euclid(6.0)
+    } else if max == rgb[1] {
+        (rgb[2] - rgb[0]) / delta + 2.0
+    } else {
+        (rgb[0] - rgb[1]) / delta + 4.0
+    };
+    (hue / 6.0, max, min)
+}
+
+// RGB from hue (0 to 1), chroma and the amount added to each component.
+fn hue_to_rgb(hue: f32, chroma: f32, m: f32) -> Col3 {
+    let h = hue.rem_euclid(1.0) * 6.0;
+    let x = chroma * (1.0 - ((h % 2.0) - 1.0).abs());
+    let (r, g, b) = match h as u32 {
+        0 => (chroma, x, 0.0),
+        1 => (x, chroma, 0.0),
+        2 => (0.0, chroma, x),
+        3 => (0.0, x, chroma),
+        4 => (x, 0.0, chroma),
+        _ => (chroma, 0.0, x),
+    };
+    [r + m, g + m, b + m]
+}
+
+fn rgb_to_hsv(rgb: Col3) -> Col3 {
+    let (hue, max, min) = rgb_hue(rgb);
+    let sat = if max == 0.0 { 0.0 } else { (max - min) / max };
+    [hue, sat, max]
+}
+
+fn hsv_to_rgb(hsv: Col3) -> Col3 {
+    let chroma = hsv[2] * hsv[1];
+    hue_to_rgb(hsv[0], chroma, hsv[2] - chroma)
+}
+
+fn rgb_to_hsl(rgb: Col3) -> Col3 {
+    let (hue, max, min) = rgb_hue(rgb);
+    let light = (max + min) / 2.0;
+    let sat = if max == min { 0.0 } else { (max - min) / (1.0 - (2.0 * light - 1.0).abs()) };
+    [hue, sat, light]
+}
+
+fn hsl_to_rgb(hsl: Col3) -> Col3 {
+    let chroma = (1.0 - (2.0 * hsl[2] - 1.0).abs()) * hsl[1];
+    hue_to_rgb(hsl[0], chroma, hsl[2] - chroma / 2.0)
+}
+
+// Product of 3x3 matrix and colour.
+fn mat_mul(m: &[[f32; 3]; 3], c: Col3) -> Col3 {
+    [
+        m[0][0] * c[0] + m[0][1] * c[1] + m[0][2] * c[2],
+        m[1][0] * c[0] + m[1][1] * c[1] + m[1][2] * c[2],
+        m[2][0] * c[0] + m[2][1] * c[1] + m[2][2] * c[2],
+    ]
+}
+
+// OKLab (Bjorn Ottosson) from linear RGB, and back.
+const OKLAB_M1: [[f32; 3]; 3] = [
+    [0.412_221_46, 0.536_332_55, 0.051_445_995],
+    [0.211_903_5, 0.680_699_5, 0.107_396_96],
+    [0.088_302_46, 0.281_718_85, 0.629_978_7],
+];
+const OKLAB_M2: [[f32; 3]; 3] = [
+    [0.210_454_26, 0.793_617_8, -0.004_072_047],
+    [1.977_998_5, -2.428_592_2, 0.450_593_7],
+    [0.025_904_037, 0.782_771_77, -0.808_675_77],
+];
+const OKLAB_M2_INV: [[f32; 3]; 3] = [
+    [1.0, 0.396_337_78, 0.215_803_76],
+    [1.0, -0.105_561_346, -0.063_854_17],
+    [1.0, -0.089_484_18, -1.291_485_5],
+];
+const OKLAB_M1_INV: [[f32; 3]; 3] = [
+    [4.076_741_7, -3.307_711_6, 0.230_969_94],
+    [-1.268_438, 2.609_757_4, -0.341_319_38],
+    [-0.004_196_086_3, -0.703_418_6, 1.707_614_7],
+];
+
+fn linear_to_oklab(rgb: Col3) -> Col3 {
+    let lms = mat_mul(&OKLAB_M1, rgb).map(f32::cbrt);
+    mat_mul(&OKLAB_M2, lms)
+}
+
+fn oklab_to_linear(lab: Col3) -> Col3 {
+    let lms = mat_mul(&OKLAB_M2_INV, lab).map(|c| c * c * c);
+    mat_mul(&OKLAB_M1_INV, lms)
+}
+
+// CIELAB (D65 white) from linear RGB via XYZ, and back.
+const XYZ_FROM_RGB: [[f32; 3]; 3] = [
+    [0.412_456_4, 0.357_576_1, 0.180_437_5],
+    [0.212_672_9, 0.715_152_2, 0.072_175],
+    [0.019_333_9, 0.119_192, 0.950_304_1],
+];
+const RGB_FROM_XYZ: [[f32; 3]; 3] = [
+    [3.240_454_2, -1.537_138_5, -0.498_531_4],
+    [-0.969_266, 1.876_010_8, 0.041_556],
+    [0.055_643_4, -0.204_025_9, 1.057_225_2],
+];
+const D65_WHITE: Col3 = [0.950_47, 1.0, 1.088_83];
+const LAB_EPSILON: f32 = 216.0 / 24389.0;
+const LAB_KAPPA: f32 = 24389.0 / 27.0;
+
+fn linear_to_cielab(rgb: Col3) -> Col3 {
+    let xyz = mat_mul(&XYZ_FROM_RGB, rgb);
+    let f = |i: usize| {
+        let r = xyz[i] / D65_WHITE[i];
+        if r > LAB_EPSILON { r.cbrt() } else { (LAB_KAPPA * r + 16.0) / 116.0 }
+    };
+    let (fx, fy, fz) = (f(0), f(1), f(2));
+    [116.0 * fy - 16.0, 500.0 * (fx - fy), 200.0 * (fy - fz)]
+}
+
+fn cielab_to_linear(lab: Col3) -> Col3 {
+    let fy = (lab[0] + 16.0) / 116.0;
+    let fx = fy + lab[1] / 500.0;
+    let fz = fy - lab[2] / 200.0;
+    let f_inv = |f: f32| {
+        let f_cubed = f * f * f;
+        if f_cubed > LAB_EPSILON { f_cubed } else { (116.0 * f - 16.0) / LAB_KAPPA }
+    };
+    let xyz = [f_inv(fx) * D65_WHITE[0], f_inv(fy) * D65_WHITE[1], f_inv(fz) * D65_WHITE[2]];
+    mat_mul(&RGB_FROM_XYZ, xyz)
+}
+
+// Parse a colour, either hex "#ff8800" (# optional),
+// or red, green, blue components "255 136 0" or "255,136,0".
+// Returns None if the text isn't a valid colour.
+pub fn parse_colour(text: &str) -> Option<(u8, u8, u8)> {
+    let text = text.trim();
+    let hex = text.strip_prefix('#').unwrap_or(text);
+    if hex.len() == 6 && hex.chars().all(|ch| ch.is_ascii_hexdigit()) {
+        let component = |idx: usize| u8::from_str_radix(&hex[idx..idx + 2], 16).ok();
+        return Some((component(0)?, component(2)?, component(4)?));
+    }
+
+    let values: Vec<u8> = text.split(|ch: char| ch == ',' || ch.is_whitespace())
+        .filter(|value| !value.is_empty())
+        .map(|value| value.parse::<u8>().ok())
+        .collect::<Option<_>>()?;
+    match values[..] {
+        [r, g, b] => Some((r, g, b)),
+        _ => None,
+    }
+}
+
+// Colour as hex text, e.g. "#ff8800".
+pub fn hex_colour(colour: (u8, u8, u8)) -> String {
+    format!("#{:02x}{:02x}{:02x}", colour.0, colour.1, colour.2)
+}
+
+// Check palete boundaries are strictly increasing.
+// Returns the index of the first boundary that isn't.
+pub fn check_boundaries(col_pal: &[(u32, (u8, u8, u8))]) -> Result<(), usize> {
+    match col_pal.windows(2).position(|pair| pair[1].0 <= pair[0].0) {
+        Some(idx) => Err(idx + 1),
+        None => Ok(()),
+    }
+}
+
+// Colour stop at a position from 0 to 1, as held in palete files.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+pub struct PaleteStop {
+    pub pos: f32,
+    pub colour: (u8, u8, u8),
+}
+
+// Palete file of colour stops, saved as JSON or TOML.
+#[derive(Serialize, Deserialize)]
+struct PaleteFile {
+    #[serde(default)]
+    interp: ColourSpace,
+    stops: Vec<PaleteStop>,
+}
+
+// Shorthand for invalid palete file errors.
+fn invalid_data(msg: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+// File extension, lower case.
+fn extension(path: &Path) -> String {
+    path.extension().map(|ext| ext.to_string_lossy().to_lowercase()).unwrap_or_default()
+}
+
+// Load a palete file, scaled to maximum iterations.
+// Format is from the extension: Fractint .map, GIMP .ggr, .json or .toml stops.
+// Returns the palete and the interpolation colour space if the file has one.
+pub fn load_palete(path: &Path, max_its: u32) -> io::Result<(ColPalete, Option<ColourSpace>)> {
+    let text = fs::read_to_string(path)?;
+    let (stops, interp) = match extension(path).as_str() {
+        "map" => (parse_map(&text)?, None),
+        "ggr" => (parse_ggr(&text)?, None),
+        "json" => {
+            let file: PaleteFile = serde_json::from_str(&text).map_err(|e| invalid_data(e.to_string()))?;
+            (file.stops, Some(file.interp))
+        }
+        "toml" => {
+            let file: PaleteFile = toml::from_str(&text).map_err(|e| invalid_data(e.to_string()))?;
+            (file.stops, Some(file.interp))
+        }
+        ext => return Err(invalid_data(format!("Unknown palete file type: {:?}", ext))),
+    };
+    if stops.is_empty() {
+        return Err(invalid_data(format!("No colours in palete file: {:?}", path)));
+    }
+    Ok((stops_to_palete(&stops, max_its), interp))
+}
+
+// Save a palete file, format from the extension as for load_palete.
+// Fractint maps and GIMP gradients don't hold the interpolation colour
+// space, so the palete is sampled or blended in RGB.
+pub fn save_palete(path: &Path, col_pal: &[(u32, (u8, u8, u8))], interp: ColourSpace) -> io::Result<()> {
+    let stops = palete_to_stops(col_pal);
+    if stops.is_empty() {
+        return Err(invalid_data("No colour palete defined".to_string()));
+    }
+    let text = match extension(path).as_str() {
+        "map" => format_map(&stops, interp),
+        "ggr" => format_ggr(&stops, path),
+        "json" => {
+            let file = PaleteFile { interp, stops };
+            serde_json::to_string_pretty(&file).map_err(|e| invalid_data(e.to_string()))?
+        }
+        "toml" => {
+            let file = PaleteFile { interp, stops };
+            toml::to_string(&file).map_err(|e| invalid_data(e.to_string()))?
+        }
+        ext => return Err(invalid_data(format!("Unknown palete file type: {:?}", ext))),
+    };
+    fs::write(path, text)
+}
+
+// Scale colour stops to iteration boundaries from 1 to maximum iterations.
+// Stops that land on the same boundary keep the first colour.
+pub fn stops_to_palete(stops: &[PaleteStop], max_its: u32) -> ColPalete {
+    let mut stops = stops.to_vec();
+    stops.sort_by(|a, b| a.pos.total_cmp(&b.pos));
+
+    let span = max_its.saturating_sub(1) as f32;
+    let mut col_pal: ColPalete = Vec::with_capacity(stops.len());
+    for stop in stops {
+        let its_bound = 1 + (stop.pos.clamp(0.0, 1.0) * span).round() as u32;
+        if col_pal.last().is_none_or(|last| its_bound > last.0) {
+            col_pal.push((its_bound, stop.colour));
+        }
+    }
+    col_pal
+}
+
+// Colour stops from palete boundaries, first boundary at 0 and last at 1.
+pub fn palete_to_stops(col_pal: &[(u32, (u8, u8, u8))]) -> Vec<PaleteStop> {
+    let (first, last) = match (col_pal.first(), col_pal.last()) {
+        (Some(first), Some(last)) => (first.0 as f32, last.0 as f32),
+        _ => return Vec::new(),
+    };
+    let span = (last - first).max(1.0);
+    col_pal.iter()
+        .map(|(its_bound, colour)| PaleteStop { pos: (*its_bound as f32 - first) / span, colour: *colour })
+        .collect()
+}
+
+// Colour at a position from 0 to 1 along colour stops.
+fn stops_colour(stops: &[PaleteStop], interp: ColourSpace, pos: f32) -> (u8, u8, u8) {
+    for pair in stops.windows(2) {
+        if pos <= pair[1].pos {
+            let width = pair[1].pos - pair[0].pos;
+            let t = if width > 0.0 { ((pos - pair[0].pos) / width).clamp(0.0, 1.0) } else { 1.0 };
+            return interp.interpolate(pair[0].colour, pair[1].colour, t);
+        }
+    }
+    stops.last().map_or((0, 0, 0), |stop| stop.colour)
+}
+
+// Fractint map, a line of "red green blue" for each of 256 colours.
+// Anything after the colour on a line is a comment.
+fn parse_map(text: &str) -> io::Result<Vec<PaleteStop>> {
+    let mut colours: Vec<(u8, u8, u8)> = Vec::with_capacity(MAP_COLOURS);
+    for line in text.lines().filter(|line| !line.trim().is_empty()) {
+        let values: Vec<u8> = line.split_whitespace()
+            .take(3)
+            .map(|value| value.parse::<u8>())
+            .collect::<Result<_, _>>()
+            .map_err(|e| invalid_data(format!("Invalid map colour {:?}: {}", line, e)))?;
+        if values.len() < 3 {
+            return Err(invalid_data(format!("Invalid map colour {:?}", line)));
+        }
+        colours.push((values[0], values[1], values[2]));
+    }
+
+    let last = colours.len().saturating_sub(1).max(1) as f32;
+    Ok(colours.iter()
+        .enumerate()
+        .map(|(idx, colour)| PaleteStop { pos: idx as f32 / last, colour: *colour })
+        .collect())
+}
+
+fn format_map(stops: &[PaleteStop], interp: ColourSpace) -> String {
+    let mut text = String::new();
+    for idx in 0..MAP_COLOURS {
+        let (r, g, b) = stops_colour(stops, interp, idx as f32 / (MAP_COLOURS - 1) as f32);
+        text.push_str(&format!("{} {} {}\n", r, g, b));
+    }
+    text
+}
+
+// GIMP gradient, a header then a line for each segment of
+// "left mid right  r g b a (left)  r g b a (right)  blend colour_type ...".
+// Colours are 0 to 1. Segments become stops at the ends, with the midpoint
+// added where the segment isn't a plain linear blend, as all blend types
+// are half way at the midpoint. Blends are approximated by the palete
+// interpolation between stops.
+// HSV segments (colour type 1 anticlockwise, 2 clockwise round the colour
+// wheel) are sampled into several stops, so the hue goes the right way
+// whatever the palete interpolation.
+fn parse_ggr(text: &str) -> io::Result<Vec<PaleteStop>> {
+    let mut lines = text.lines();
+    if lines.next().map(str::trim) != Some("GIMP Gradient") {
+        return Err(invalid_data("Not a GIMP gradient file".to_string()));
+    }
+
+    // Optional name line, then the number of segments.
+    let mut line = lines.next().unwrap_or_default();
+    if line.starts_with("Name:") {
+        line = lines.next().unwrap_or_default();
+    }
+    let num_segments: usize = line.trim().parse()
+        .map_err(|e| invalid_data(format!("Invalid gradient segment count {:?}: {}", line, e)))?;
+
+    let mut stops: Vec<PaleteStop> = Vec::with_capacity(num_segments * 3);
+    for line in lines.take(num_segments) {
+        let values: Vec<f32> = line.split_whitespace()
+            .map(|value| value.parse::<f32>())
+            .collect::<Result<_, _>>()
+            .map_err(|e| invalid_data(format!("Invalid gradient segment {:?}: {}", line, e)))?;
+        if values.len() < 11 {
+            return Err(invalid_data(format!("Invalid gradient segment {:?}", line)));
+        }
+        let (left, mid, right) = (values[0], values[1], values[2]);
+        let col_left = [values[3], values[4], values[5]];
+        let col_right = [values[7], values[8], values[9]];
+        let blend = values.get(11).copied().unwrap_or(0.0);
+        let colour_type = values.get(12).copied().unwrap_or(0.0);
+
+        match colour_type {
+            0.0 => {
+                stops.push(PaleteStop { pos: left, colour: from_unit(col_left) });
+                if blend != 0.0 || (mid - (left + right) / 2.0).abs() > 1.0e-4 {
+                    stops.push(PaleteStop { pos: mid, colour: from_unit(lerp3(col_left, col_right, 0.5)) });
+                }
+                stops.push(PaleteStop { pos: right, colour: from_unit(col_right) });
+            }
+            1.0 | 2.0 => {
+                // Position along the segment, half way at the midpoint.
+                let mid_t = if right > left { ((mid - left) / (right - left)).clamp(1.0e-4, 1.0 - 1.0e-4) } else { 0.5 };
+                for step in 0..=GGR_HSV_STEPS {
+                    let t = step as f32 / GGR_HSV_STEPS as f32;
+                    let blend_t = if t <= mid_t { 0.5 * t / mid_t } else { 0.5 + 0.5 * (t - mid_t) / (1.0 - mid_t) };
+                    let colour = ggr_hsv_colour(col_left, col_right, blend_t, colour_type == 2.0);
+                    stops.push(PaleteStop { pos: left + t * (right - left), colour: from_unit(colour) });
+                }
+            }
+            _ => return Err(invalid_data(format!("Unknown gradient colour type {:?}", line))),
+        }
+    }
+    if stops.len() < 2 * num_segments {
+        return Err(invalid_data("Missing gradient segments".to_string()));
+    }
+    Ok(stops)
+}
+
+// Colour part way along a GIMP gradient HSV segment, with the hue
+// going anticlockwise (increasing), or clockwise, round the colour wheel.
+fn ggr_hsv_colour(col_left: Col3, col_right: Col3, t: f32, clockwise: bool) -> Col3 {
+    let hsv_left = rgb_to_hsv(col_left);
+    let hsv_right = rgb_to_hsv(col_right);
+    let mut d_hue = hsv_right[0] - hsv_left[0];
+    if clockwise && d_hue > 0.0 {
+        d_hue -= 1.0;
+    } else if !clockwise && d_hue < 0.0 {
+        d_hue += 1.0;
+    }
+    hsv_to_rgb([
+        (hsv_left[0] + t * d_hue).rem_euclid(1.0),
+        hsv_left[1] + t * (hsv_right[1] - hsv_left[1]),
+        hsv_left[2] + t * (hsv_right[2] - hsv_left[2]),
+    ])
+}
+
+fn format_ggr(stops: &[PaleteStop], path: &Path) -> String {
+    let name = path.file_stem().map(|stem| stem.to_string_lossy().into_owned()).unwrap_or_default();
+    let segments: Vec<&[PaleteStop]> = stops.windows(2).collect();
+    let mut text = format!("GIMP Gradient\nName: {}\n{}\n", name, segments.len().max(1));
+
+    let unit = |colour: (u8, u8, u8)| to_unit(colour).map(|c| format!("{:.6}", c)).join(" ");
+    if segments.is_empty() {
+        // Single colour, one flat segment.
+        let colour = unit(stops[0].colour);
+        text.push_str(&format!("0.000000 0.500000 1.000000 {} 1.000000 {} 1.000000 0 0\n", colour, colour));
+    }
+    for pair in segments {
+        let (left, right) = (pair[0].pos, pair[1].pos);
+        text.push_str(&format!("{:.6} {:.6} {:.6} {} 1.000000 {} 1.000000 0 0\n",
+            left, (left + right) / 2.0, right, unit(pair[0].colour), unit(pair[1].colour)));
+    }
+    text
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fractal::test_temp_path;
+
+    const PALETE: [(u32, (u8, u8, u8)); 4] = [
+        (1, (0, 0, 64)),
+        (86, (255, 128, 0)),
+        (171, (255, 255, 255)),
+        (256, (0, 0, 0)),
+    ];
+
+    // Load a palete file from text.
+    fn load_text(name: &str, ext: &str, text: &str) -> io::Result<(ColPalete, Option<ColourSpace>)> {
+        let path = test_temp_path(&format!("palete_{}", name), ext);
+        fs::write(&path, text).unwrap();
+        let result = load_palete(&path, 256);
+        fs::remove_file(&path).unwrap();
+        result
+    }
+
+    // Save and load a palete file.
+    fn round_trip(ext: &str, interp: ColourSpace) -> (ColPalete, Option<ColourSpace>) {
+        let path = test_temp_path("palete_round_trip", ext);
+        save_palete(&path, &PALETE, interp).unwrap();
+        let result = load_palete(&path, 256).unwrap();
+        fs::remove_file(&path).unwrap();
+        result
+    }
+
+    #[test]
+    fn json_toml_round_trip() {
+        for ext in ["json", "toml"] {
+            let (col_pal, interp) = round_trip(ext, ColourSpace::Oklab);
+            assert_eq!(col_pal, PALETE, "{}", ext);
+            assert_eq!(interp, Some(ColourSpace::Oklab), "{}", ext);
+        }
+    }
+
+    #[test]
+    fn ggr_round_trip() {
+        let (col_pal, interp) = round_trip("ggr", ColourSpace::Srgb);
+        assert_eq!(col_pal, PALETE);
+        assert_eq!(interp, None);
+    }
+
+    #[test]
+    fn map_round_trip() {
+        // Maps hold a colour for each of 256 steps, one per boundary here.
+        let (col_pal, interp) = round_trip("map", ColourSpace::Srgb);
+        assert_eq!(col_pal.len(), MAP_COLOURS);
+        for (its_bound, colour) in PALETE {
+            assert_eq!(col_pal[its_bound as usize - 1], (its_bound, colour));
+        }
+        assert_eq!(interp, None);
+    }
+
+    #[test]
+    fn ggr_hsv_segments() {
+        // Red to blue anticlockwise is through green, clockwise through magenta.
+        let gradient = |colour_type: u32| format!(
+            "GIMP Gradient\nName: hsv\n1\n0 0.5 1 1 0 0 1 0 0 1 1 0 {}\n", colour_type);
+        let (ccw, _) = load_text("hsv_ccw", "ggr", &gradient(1)).unwrap();
+        let (cw, _) = load_text("hsv_cw", "ggr", &gradient(2)).unwrap();
+        let (rgb, _) = load_text("hsv_rgb", "ggr", &gradient(0)).unwrap();
+        assert_eq!(ccw.len(), GGR_HSV_STEPS + 1);
+        assert_eq!(ccw[GGR_HSV_STEPS / 2].1, (0, 255, 0));
+        assert_eq!(cw[GGR_HSV_STEPS / 2].1, (255, 0, 255));
+        assert_eq!(ccw.first().unwrap().1, (255, 0, 0));
+        assert_eq!(ccw.last().unwrap().1, (0, 0, 255));
+        assert_eq!(rgb, [(1, (255, 0, 0)), (256, (0, 0, 255))]);
+    }
+
+    #[test]
+    fn malformed_map() {
+        for text in ["", "1 2\n", "300 0 0\n", "0 0 zero\n", "0 0 0\n-1 0 0\n"] {
+            assert!(load_text("bad", "map", text).is_err(), "{:?}", text);
+        }
+        assert!(load_text("ok", "map", "0 0 0 black\n255 255 255 white\n").is_ok());
+    }
+
+    #[test]
+    fn malformed_ggr() {
+        for text in [
+            "",
+            "Not a gradient\n1\n0 0.5 1 0 0 0 1 1 1 1 1 0 0\n",
+            "GIMP Gradient\nName: bad\nmany\n",
+            "GIMP Gradient\nName: bad\n2\n0 0.25 0.5 0 0 0 1 1 1 1 1 0 0\n",
+            "GIMP Gradient\nName: bad\n1\n0 0.5 1 0 0 0 1 1 1\n",
+            "GIMP Gradient\nName: bad\n1\n0 0.5 1 0 0 0 1 x 1 1 1 0 0\n",
+            "GIMP Gradient\nName: bad\n1\n0 0.5 1 0 0 0 1 1 1 1 1 0 3\n",
+        ] {
+            assert!(load_text("bad", "ggr", text).is_err(), "{:?}", text);
+        }
+    }
+
+    #[test]
+    fn malformed_json_toml() {
+        for text in ["", "{", r#"{"stops": []}"#, r#"{"stops": [{"pos": 0.0, "colour": [256, 0, 0]}]}"#,
+                     r#"{"interp": {"type": "rainbow"}, "stops": [{"pos": 0.0, "colour": [0, 0, 0]}]}"#] {
+            assert!(load_text("bad", "json", text).is_err(), "{:?}", text);
+        }
+        for text in ["", "stops = 1", "stops = []", "[[stops]]\npos = 0.0\ncolour = [0, 0]\n"] {
+            assert!(load_text("bad", "toml", text).is_err(), "{:?}", text);
+        }
+        assert!(load_text("bad", "txt", "0 0 0\n").is_err());
+    }
+
+    const COLOUR_SPACES: [ColourSpace; 6] = [
+        ColourSpace::Srgb,
+        ColourSpace::LinearRgb,
+        ColourSpace::Hsv,
+        ColourSpace::Hsl,
+        ColourSpace::Oklab,
+        ColourSpace::Cielab,
+    ];
+
+    // Colours with every component from 0 to 255 in steps of 51.
+    fn test_colours() -> Vec<(u8, u8, u8)> {
+        let mut colours = Vec::new();
+        for r in (0..=255).step_by(51) {
+            for g in (0..=255).step_by(51) {
+                for b in (0..=255).step_by(51) {
+                    colours.push((r, g, b));
+                }
+            }
+        }
+        colours
+    }
+
+    // Colours within a byte of each other in every component.
+    fn assert_close(col: (u8, u8, u8), expected: (u8, u8, u8), msg: &str) {
+        let close = |a: u8, b: u8| a.abs_diff(b) <= 1;
+        assert!(close(col.0, expected.0) && close(col.1, expected.1) && close(col.2, expected.2),
+            "{}: {:?} expected {:?}", msg, col, expected);
+    }
+
+    // Interpolation starts and ends at the two colours exactly.
+    #[test]
+    fn interpolate_endpoints() {
+        let colours = test_colours();
+        for space in COLOUR_SPACES {
+            for (col_0, col_1) in colours.iter().zip(colours.iter().rev()) {
+                assert_eq!(space.interpolate(*col_0, *col_1, 0.0), *col_0, "{}", space);
+                assert_eq!(space.interpolate(*col_0, *col_1, 1.0), *col_1, "{}", space);
+            }
+        }
+    }
+
+    // Colours converted to each colour space and back are unchanged.
+    #[test]
+    fn colour_space_round_trips() {
+        type RoundTrip = fn(Col3) -> Col3;
+        let round_trips: [(&str, RoundTrip); 5] = [
+            ("Linear RGB", |rgb| linear_to_srgb(srgb_to_linear(rgb))),
+            ("HSV", |rgb| hsv_to_rgb(rgb_to_hsv(rgb))),
+            ("HSL", |rgb| hsl_to_rgb(rgb_to_hsl(rgb))),
+            ("OKLab", |rgb| linear_to_srgb(oklab_to_linear(linear_to_oklab(srgb_to_linear(rgb))))),
+            ("CIELAB", |rgb| linear_to_srgb(cielab_to_linear(linear_to_cielab(srgb_to_linear(rgb))))),
+        ];
+        for (name, round_trip) in round_trips {
+            for col in test_colours() {
+                assert_eq!(from_unit(round_trip(to_unit(col))), col, "{}", name);
+            }
+        }
+    }
+
+    // Red to blue midpoints, from double precision reference conversions.
+    // HSV and HSL go the shortest way round the hue circle, through magenta.
+    #[test]
+    fn interpolate_midpoints() {
+        let (red, blue) = ((255, 0, 0), (0, 0, 255));
+        for (space, expected) in [
+            (ColourSpace::Srgb, (127, 0, 127)),
+            (ColourSpace::LinearRgb, (188, 0, 188)),
+            (ColourSpace::Hsv, (255, 0, 255)),
+            (ColourSpace::Hsl, (255, 0, 255)),
+            (ColourSpace::Oklab, (140, 83, 162)),
+            (ColourSpace::Cielab, (202, 0, 136)),
+        ] {
+            assert_close(space.interpolate(red, blue, 0.5), expected, &space.to_string());
+        }
+    }
+
+    // Boundaries at the quantiles of the counts outside the set,
+    // with points inside the set (at maximum iterations) left out.
+    #[test]
+    fn auto_palete_quantiles() {
+        let base = &BUILTIN_PALETES[0];
+        let base_stops = base.palete_stops();
+        let mut escape_its: Vec<Vec<u32>> = (0..10).map(|row| (1..=10).map(|col| row * 10 + col).collect()).collect();
+        escape_its.push(vec![200; 50]);
+
+        let col_pal = auto_palete(&escape_its, 200, base, 4);
+        let bounds: Vec<u32> = col_pal.iter().map(|(its_bound, _)| *its_bound).collect();
+        assert_eq!(bounds, [0, 26, 51, 75, 200]);
+        for (idx, (_, colour)) in col_pal.iter().enumerate() {
+            assert_eq!(*colour, stops_colour(&base_stops, base.interp, idx as f32 / 4.0));
+        }
+
+        // Mostly one count, so the boundaries on it are merged.
+        let escape_its = vec![vec![2; 90], (50..60).collect()];
+        let col_pal = auto_palete(&escape_its, 200, base, 4);
+        let bounds: Vec<u32> = col_pal.iter().map(|(its_bound, _)| *its_bound).collect();
+        assert_eq!(bounds, [1, 2, 200]);
+    }
+}
@@ -0,0 +1,359 @@
+// Binary results file for fractal divergence results.
+// Saved alongside the TOML settings file, so that the settings
+// stay human readable and the results are quick to save and load.
+//
+// File layout (little endian):
+//   magic       [u8; 4]  "FRES"
+//   version     u16
+//   rows        u32
+//   cols        u32
+//   max_its     u32
+//   num_grids   u16
+//   checksum    u32      CRC32 of the uncompressed grid data
+//   grid data   zlib compressed, for each grid:
+//       tag     [u8; 4]
+//       values  rows x cols 4 byte values, row by row
+//
+// Grids are streamed a row at a time, so large images don't need
+// a second copy of the results in memory. The checksum is written
+// into the header once all the grids have been written.
+// Optional grids are only saved for the modes that use them.
+
+use flate2::read::ZlibDecoder;
+use flate2::write::ZlibEncoder;
+use flate2::Compression;
+use std::fs::File;
+use std::io::{self, BufReader, BufWriter, Read, Seek, SeekFrom, Write};
+use std::path::Path;
+
+use crate::fractal::Fractal;
+
+// File identifier and current version.
+const RESULTS_MAGIC: &[u8; 4] = b"FRES";
+const RESULTS_VERSION: u16 = 1;
+
+// Offset of the checksum in the header.
+const CHECKSUM_OFFSET: u64 = 20;
+
+// Grid tags.
+const TAG_ESCAPE_ITS: &[u8; 4] = b"ITS ";
+const TAG_ESCAPE_MU: &[u8; 4] = b"MU  ";
+const TAG_ESCAPE_INT: &[u8; 4] = b"INT ";
+const TAG_ESCAPE_DIST: &[u8; 4] = b"DIST";
+const TAG_ESCAPE_TRAP: &[u8; 4] = b"TRAP";
+
+// Extension for results files.
+pub const RESULTS_EXT: &str = "fres";
+
+// Shorthand for invalid results file errors.
+fn invalid_data(msg: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+// Writer that keeps a running checksum of the data written.
+struct CrcWriter<W: Write> {
+    inner: W,
+    hasher: crc32fast::Hasher,
+}
+
+impl<W: Write> Write for CrcWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let len = self.inner.write(buf)?;
+        self.hasher.update(&buf[..len]);
+        Ok(len)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+// Save fractal results to a binary results file.
+pub fn save_results(path: &Path, fractals: &Fractal) -> io::Result<()> {
+    // Grids to save, optional grids only if their mode uses them, and they
+    // were calculated, as the mode may have changed since calculating.
+    let (rows, cols) = (fractals.rows as usize, fractals.cols as usize);
+    let calculated = |grid: &Vec<Vec<f32>>| grid.len() == rows && grid.iter().all(|row| row.len() == cols);
+    let mut f32_grids: Vec<(&[u8; 4], &Vec<Vec<f32>>)> = vec![(TAG_ESCAPE_MU, &fractals.escape_mu)];
+    if fractals.needs_interior() && calculated(&fractals.escape_int) {
+        f32_grids.push((TAG_ESCAPE_INT, &fractals.escape_int));
+    }
+    if fractals.needs_distance() && calculated(&fractals.escape_dist) {
+        f32_grids.push((TAG_ESCAPE_DIST, &fractals.escape_dist));
+    }
+    if fractals.orbit_trap.is_on() && calculated(&fractals.escape_trap) {
+        f32_grids.push((TAG_ESCAPE_TRAP, &fractals.escape_trap));
+    }
+    let num_grids = 1 + f32_grids.len() as u16;
+
+    // Write header, with the checksum filled in at the end.
+    let mut writer = BufWriter::new(File::create(path)?);
+    writer.write_all(RESULTS_MAGIC)?;
+    writer.write_all(&RESULTS_VERSION.to_le_bytes())?;
+    writer.write_all(&fractals.rows.to_le_bytes())?;
+    writer.write_all(&fractals.cols.to_le_bytes())?;
+    writer.write_all(&fractals.max_its.to_le_bytes())?;
+    writer.write_all(&num_grids.to_le_bytes())?;
+    writer.write_all(&0u32.to_le_bytes())?;
+
+    // Write compressed grid data, row by row, checksumming the uncompressed data.
+    let mut grid_writer = CrcWriter {
+        inner: ZlibEncoder::new(writer, Compression::default()),
+        hasher: crc32fast::Hasher::new(),
+    };
+    let mut row_bytes: Vec<u8> = Vec::with_capacity(fractals.cols as usize * 4);
+    grid_writer.write_all(TAG_ESCAPE_ITS)?;
+    for row in &fractals.escape_its {
+        row_bytes.clear();
+        row.iter().for_each(|its| row_bytes.extend_from_slice(&its.to_le_bytes()));
+        grid_writer.write_all(&row_bytes)?;
+    }
+    for (tag, grid) in f32_grids {
+        grid_writer.write_all(tag)?;
+        for row in grid {
+            row_bytes.clear();
+            row.iter().for_each(|value| row_bytes.extend_from_slice(&value.to_le_bytes()));
+            grid_writer.write_all(&row_bytes)?;
+        }
+    }
+    let checksum = grid_writer.hasher.finalize();
+
+    // Fill in the checksum.
+    let mut writer = grid_writer.inner.finish()?;
+    writer.seek(SeekFrom::Start(CHECKSUM_OFFSET))?;
+    writer.write_all(&checksum.to_le_bytes())?;
+    writer.flush()?;
+
+    Ok(())
+}
+
+// Load fractal results from a binary results file.
+// The fractal must already be initialised from its settings,
+// as the file must match its dimensions.
+// If the file is invalid the fractal is left without results.
+pub fn load_results(path: &Path, fractals: &mut Fractal) -> io::Result<()> {
+    fractals.has_results = false;
+    let mut reader = BufReader::new(File::open(path)?);
+
+    // Read and check header.
+    let mut magic = [0u8; 4];
+    reader.read_exact(&mut magic)?;
+    if &magic != RESULTS_MAGIC {
+        return Err(invalid_data(format!("Not a fractal results file: {:?}", path)));
+    }
+    let version = read_u16(&mut reader)?;
+    if version > RESULTS_VERSION {
+        return Err(invalid_data(format!("Unsupported results file version: {}", version)));
+    }
+    let rows = read_u32(&mut reader)?;
+    let cols = read_u32(&mut reader)?;
+    let max_its = read_u32(&mut reader)?;
+    if rows != fractals.rows || cols != fractals.cols || max_its != fractals.max_its {
+        return Err(invalid_data(format!(
+            "Results file ({} x {}, max its {}) doesn't match settings ({} x {}, max its {})",
+            rows, cols, max_its, fractals.rows, fractals.cols, fractals.max_its)));
+    }
+    let num_grids = read_u16(&mut reader)?;
+    let checksum = read_u32(&mut reader)?;
+
+    // Unpack the grids row by row, skipping any that aren't known
+    // or aren't used, checksumming the uncompressed data.
+    let mut decoder = ZlibDecoder::new(reader);
+    let mut hasher = crc32fast::Hasher::new();
+    let mut row_bytes: Vec<u8> = vec![0; cols as usize * 4];
+    let truncated = |e: io::Error| match e.kind() {
+        io::ErrorKind::UnexpectedEof => invalid_data(format!("Results file data is truncated: {:?}", path)),
+        _ => e,
+    };
+    for _ in 0..num_grids {
+        let mut tag = [0u8; 4];
+        decoder.read_exact(&mut tag).map_err(truncated)?;
+        hasher.update(&tag);
+        for row in 0..rows as usize {
+            decoder.read_exact(&mut row_bytes).map_err(truncated)?;
+            hasher.update(&row_bytes);
+            let words = row_bytes.chunks_exact(4).map(|w| [w[0], w[1], w[2], w[3]]);
+            let f32_grid = match &tag {
+                TAG_ESCAPE_ITS => {
+                    fractals.escape_its[row].iter_mut().zip(words).for_each(|(its, w)| *its = u32::from_le_bytes(w));
+                    continue;
+                }
+                TAG_ESCAPE_MU => &mut fractals.escape_mu,
+                TAG_ESCAPE_INT => &mut fractals.escape_int,
+                TAG_ESCAPE_DIST => &mut fractals.escape_dist,
+                TAG_ESCAPE_TRAP => &mut fractals.escape_trap,
+                _ => continue,
+            };
+            if let Some(grid_row) = f32_grid.get_mut(row) {
+                grid_row.iter_mut().zip(words).for_each(|(value, w)| *value = f32::from_le_bytes(w));
+            }
+        }
+    }
+
+    // Nothing should follow the grids.
+    if decoder.read(&mut [0u8; 1])? != 0 {
+        return Err(invalid_data(format!("Results file has extra data: {:?}", path)));
+    }
+    if hasher.finalize() != checksum {
+        return Err(invalid_data(format!("Results file checksum mismatch: {:?}", path)));
+    }
+    fractals.has_results = true;
+
+    Ok(())
+}
+
+// Read little endian u16.
+fn read_u16<R: Read>(reader: &mut R) -> io::Result<u16> {
+    let mut buf = [0u8; 2];
+    reader.read_exact(&mut buf)?;
+    Ok(u16::from_le_bytes(buf))
+}
+
+// Read little endian u32.
+fn read_u32<R: Read>(reader: &mut R) -> io::Result<u32> {
+    let mut buf = [0u8; 4];
+    reader.read_exact(&mut buf)?;
+    Ok(u32::from_le_bytes(buf))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::colouring::{DistanceMode, InteriorMode};
+    use crate::fractal::{test_fractal, test_temp_path};
+    use std::fs;
+
+    const VIEW: &str = r#"
+        rows = 30
+        cols = 40
+        mid_pt = ["-0.6", "0"]
+        pt_div = "0.07"
+        max_its = 100
+        col_palete = []
+    "#;
+
+    // Fractal with distinct values in every grid.
+    fn filled_fractal() -> Fractal {
+        let mut fractals = test_fractal(VIEW);
+        fractals.distance_mode = DistanceMode::AntiAlias;
+        fractals.init_fractal_image(fractals.rows, fractals.cols, fractals.mid_pt, fractals.pt_div);
+        for row in 0..fractals.rows as usize {
+            for col in 0..fractals.cols as usize {
+                let idx = (row * fractals.cols as usize + col) as u32;
+                fractals.escape_its[row][col] = idx % fractals.max_its;
+                fractals.escape_mu[row][col] = idx as f32 * 0.25;
+                fractals.escape_dist[row][col] = idx as f32 * 0.5;
+            }
+        }
+        fractals.has_results = true;
+        fractals
+    }
+
+    // Fresh fractal of the same size to load into.
+    fn empty_fractal() -> Fractal {
+        let mut fractals = test_fractal(VIEW);
+        fractals.distance_mode = DistanceMode::AntiAlias;
+        fractals.init_fractal_image(fractals.rows, fractals.cols, fractals.mid_pt, fractals.pt_div);
+        fractals
+    }
+
+    #[test]
+    fn round_trip() {
+        let path = test_temp_path("round_trip", RESULTS_EXT);
+        let saved = filled_fractal();
+        save_results(&path, &saved).unwrap();
+
+        let mut loaded = empty_fractal();
+        load_results(&path, &mut loaded).unwrap();
+        fs::remove_file(&path).unwrap();
+
+        assert!(loaded.has_results);
+        assert_eq!(saved.escape_its, loaded.escape_its);
+        assert_eq!(saved.escape_mu, loaded.escape_mu);
+        assert_eq!(saved.escape_dist, loaded.escape_dist);
+    }
+
+    // Changing mode after calculating leaves the new mode's grid empty,
+    // so it isn't saved, and the settings and results still load.
+    #[test]
+    fn save_load_after_mode_change() {
+        let path = test_temp_path("mode_change", "toml");
+        let mut saved = filled_fractal();
+        saved.interior_mode = InteriorMode::Magnitude;
+        saved.save_config(path.to_str().unwrap()).unwrap();
+
+        let mut loaded = test_fractal(VIEW);
+        let result = loaded.load_config(path.to_str().unwrap(), true);
+        fs::remove_file(&path).unwrap();
+        fs::remove_file(path.with_extension(RESULTS_EXT)).unwrap();
+        assert_eq!(result.unwrap(), None);
+
+        assert!(loaded.has_results);
+        assert_eq!(loaded.interior_mode, InteriorMode::Magnitude);
+        assert_eq!(saved.escape_its, loaded.escape_its);
+        assert_eq!(saved.escape_mu, loaded.escape_mu);
+    }
+
+    // Settings still load without their results file, leaving results to be recalculated.
+    #[test]
+    fn settings_load_without_results_file() {
+        let path = test_temp_path("missing", "toml");
+        let mut saved = filled_fractal();
+        saved.save_config(path.to_str().unwrap()).unwrap();
+        fs::remove_file(path.with_extension(RESULTS_EXT)).unwrap();
+
+        let mut loaded = test_fractal(VIEW);
+        loaded.has_results = true;
+        let result = loaded.load_config(path.to_str().unwrap(), true);
+        fs::remove_file(&path).unwrap();
+
+        let warning = result.unwrap().expect("Missing results file not warned about");
+        assert!(warning.contains(RESULTS_EXT));
+        assert!(!loaded.has_results);
+        assert_eq!(loaded.distance_mode, DistanceMode::AntiAlias);
+    }
+
+    #[test]
+    fn corrupt_file() {
+        let path = test_temp_path("corrupt", RESULTS_EXT);
+        save_results(&path, &filled_fractal()).unwrap();
+        let mut bytes = fs::read(&path).unwrap();
+        let last = bytes.len() - 10;
+        bytes[last] ^= 0xff;
+        fs::write(&path, &bytes).unwrap();
+
+        let mut loaded = empty_fractal();
+        let result = load_results(&path, &mut loaded);
+        fs::remove_file(&path).unwrap();
+        assert!(result.is_err());
+        assert!(!loaded.has_results);
+    }
+
+    #[test]
+    fn truncated_file() {
+        let path = test_temp_path("truncated", RESULTS_EXT);
+        save_results(&path, &filled_fractal()).unwrap();
+        let bytes = fs::read(&path).unwrap();
+        fs::write(&path, &bytes[..bytes.len() / 2]).unwrap();
+
+        let mut loaded = empty_fractal();
+        let result = load_results(&path, &mut loaded);
+        fs::remove_file(&path).unwrap();
+        assert!(result.is_err());
+        assert!(!loaded.has_results);
+    }
+
+    #[test]
+    fn wrong_checksum() {
+        let path = test_temp_path("checksum", RESULTS_EXT);
+        save_results(&path, &filled_fractal()).unwrap();
+        let mut bytes = fs::read(&path).unwrap();
+        bytes[CHECKSUM_OFFSET as usize] ^= 0xff;
+        fs::write(&path, &bytes).unwrap();
+
+        let mut loaded = empty_fractal();
+        let result = load_results(&path, &mut loaded);
+        fs::remove_file(&path).unwrap();
+        assert_eq!(result.unwrap_err().kind(), io::ErrorKind::InvalidData);
+    }
+}
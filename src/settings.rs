@@ -1,5 +1,7 @@
 use serde::{Deserialize};
 
+use std::thread;
+
 #[derive(Debug, Deserialize, Clone)]
 pub struct Settings {
     pub program_name: String,
@@ -9,4 +11,20 @@ pub struct Settings {
     pub fractals_folder: String,
     pub fractal_file: String,
     pub hist_plot_log: bool,
+    #[serde(default = "default_num_workers")]
+    pub num_workers: usize,
+    #[serde(default = "default_interior_checks")]
+    pub interior_checks: bool,
+    #[serde(default)]
+    pub subdivision: bool,
+}
+
+// Worker threads for settings files without num_workers, one per core.
+fn default_num_workers() -> usize {
+    thread::available_parallelism().map_or(1, |cores| cores.get())
+}
+
+// Interior checks are on for settings files without interior_checks.
+fn default_interior_checks() -> bool {
+    true
 }
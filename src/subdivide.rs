@@ -0,0 +1,258 @@
+// Rectangle subdivision (Mariani-Silver) calculation of fractal divergence.
+// The image is split into tiles, and for each tile the border of a
+// rectangle is calculated. If every border point is found to be inside
+// the set by the interior checks (known interior, or the orbit settled
+// into a cycle) the inside of the rectangle is filled without calculating
+// it, otherwise the rectangle is split into four and each is checked in turn.
+// Relies on the set having no holes, which is only the case for the
+// connected Mandelbrot set, so other formulas and Julia sets are
+// calculated point by point. Outside the set isn't filled, as small
+// copies of the set can sit inside a rectangle with a uniform border.
+// Border points that only reach maximum iterations aren't enough either,
+// as filaments of the outside finer than the points can cross the border.
+// Filling is only done for the solid interior colouring mode without
+// orbit traps, as other modes need data from each point's orbit.
+
+use num_complex::Complex;
+
+use crate::colouring::InteriorMode;
+use crate::formula::FormulaType;
+use crate::fractal::{Fractal, PointResult};
+
+// Size of the tiles shared out between worker threads.
+const TILE_SIZE: u32 = 64;
+
+// Rectangles this size or smaller are calculated point by point.
+const MIN_RECT_SIZE: u32 = 4;
+
+// Tile of the image, with inclusive row and column limits.
+#[derive(Debug, Clone, Copy)]
+pub struct Tile {
+    pub row_0: u32,
+    pub col_0: u32,
+    pub row_1: u32,
+    pub col_1: u32,
+}
+
+impl Tile {
+    pub fn rows(&self) -> u32 {
+        self.row_1 - self.row_0 + 1
+    }
+
+    pub fn cols(&self) -> u32 {
+        self.col_1 - self.col_0 + 1
+    }
+}
+
+// Split the image into tiles.
+pub fn image_tiles(rows: u32, cols: u32) -> Vec<Tile> {
+    let mut tiles: Vec<Tile> = Vec::new();
+    for row_0 in (0..rows).step_by(TILE_SIZE as usize) {
+        for col_0 in (0..cols).step_by(TILE_SIZE as usize) {
+            tiles.push(Tile {
+                row_0,
+                col_0,
+                row_1: (row_0 + TILE_SIZE).min(rows) - 1,
+                col_1: (col_0 + TILE_SIZE).min(cols) - 1,
+            });
+        }
+    }
+    tiles
+}
+
+// Calculate divergence for all points in a tile by subdivision.
+// Results are returned row by row for the tile.
+// row_ims and col_res are the imaginary and real parts of the points
+// for each row and column, as for the row by row calculation.
+pub fn cal_tile(fractals: &Fractal, tile: Tile, row_ims: &[f64], col_res: &[f64]) -> Vec<PointResult> {
+    let mut tile_calc = TileCalc {
+        fractals,
+        tile,
+        row_ims,
+        col_res,
+        results: vec![None; (tile.rows() * tile.cols()) as usize],
+    };
+    tile_calc.subdivide(tile.row_0, tile.col_0, tile.row_1, tile.col_1);
+
+    // Every point has now been calculated or filled.
+    tile_calc.results.into_iter().map(|result| result.unwrap_or_default()).collect()
+}
+
+// Working state for calculating a tile.
+struct TileCalc<'a> {
+    fractals: &'a Fractal,
+    tile: Tile,
+    row_ims: &'a [f64],
+    col_res: &'a [f64],
+    results: Vec<Option<PointResult>>,
+}
+
+impl TileCalc<'_> {
+    // Index of a point in the tile results.
+    fn idx(&self, row: u32, col: u32) -> usize {
+        ((row - self.tile.row_0) * self.tile.cols() + (col - self.tile.col_0)) as usize
+    }
+
+    // Result for a point, calculating it if not done already.
+    fn point(&mut self, row: u32, col: u32) -> PointResult {
+        let idx = self.idx(row, col);
+        if let Some(result) = self.results[idx] {
+            return result;
+        }
+        let pt = Complex::new(self.col_res[col as usize], self.row_ims[row as usize]);
+        let result = self.fractals.cal_point_divergence(row, col, pt);
+        self.results[idx] = Some(result);
+        result
+    }
+
+    // Calculate or fill the rectangle with inclusive limits.
+    fn subdivide(&mut self, row_0: u32, col_0: u32, row_1: u32, col_1: u32) {
+        // Calculate the border, checking if it is all found inside the set.
+        let mut inside = true;
+        let border = (col_0..=col_1).flat_map(|col| [(row_0, col), (row_1, col)])
+            .chain((row_0..=row_1).flat_map(|row| [(row, col_0), (row, col_1)]));
+        for (row, col) in border {
+            inside &= self.point(row, col).short_cut;
+        }
+
+        // Nothing inside the border.
+        if row_1 - row_0 < 2 || col_1 - col_0 < 2 {
+            return;
+        }
+
+        if inside && can_fill(self.fractals) {
+            self.fill(row_0, col_0, row_1, col_1);
+        } else if row_1 - row_0 <= MIN_RECT_SIZE && col_1 - col_0 <= MIN_RECT_SIZE {
+            for row in row_0 + 1..row_1 {
+                for col in col_0 + 1..col_1 {
+                    self.point(row, col);
+                }
+            }
+        } else {
+            // Split into four, sharing borders.
+            let row_m = (row_0 + row_1) / 2;
+            let col_m = (col_0 + col_1) / 2;
+            self.subdivide(row_0, col_0, row_m, col_m);
+            self.subdivide(row_0, col_m, row_m, col_1);
+            self.subdivide(row_m, col_0, row_1, col_m);
+            self.subdivide(row_m, col_m, row_1, col_1);
+        }
+    }
+
+    // Fill the inside of a rectangle with a border found inside the set.
+    // Points inside the set are at maximum iterations, with no
+    // distance estimate, and the same solid interior value as the
+    // border, so are the same as if calculated.
+    fn fill(&mut self, row_0: u32, col_0: u32, row_1: u32, col_1: u32) {
+        let corner = self.results[self.idx(row_0, col_0)].unwrap_or_default();
+        for row in row_0 + 1..row_1 {
+            for col in col_0 + 1..col_1 {
+                let idx = self.idx(row, col);
+                self.results[idx] = Some(PointResult {
+                    its: self.fractals.max_its,
+                    mu: self.fractals.max_its as f32,
+                    interior: corner.interior,
+                    dist: 0.0,
+                    filled: true,
+                    ..Default::default()
+                });
+            }
+        }
+    }
+}
+
+// True if rectangles inside the set can be filled for the fractal.
+fn can_fill(fractals: &Fractal) -> bool {
+    fractals.formula == FormulaType::Mandelbrot
+        && !fractals.julia
+        && fractals.interior_mode == InteriorMode::Solid
+        && !fractals.orbit_trap.is_on()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fractal::test_fractal;
+    use crate::menu::cal_divergence;
+
+    // Whole set, seahorse valley, a small copy of the set,
+    // Burning Ship and a disconnected Julia set.
+    const VIEWS: [&str; 5] = [
+        r#"
+            rows = 150
+            cols = 200
+            mid_pt = ["-0.6", "0"]
+            pt_div = "0.015"
+            max_its = 200
+            col_palete = []
+        "#,
+        r#"
+            rows = 150
+            cols = 200
+            mid_pt = ["-0.745", "0.11"]
+            pt_div = "0.00005"
+            max_its = 500
+            col_palete = []
+        "#,
+        r#"
+            rows = 150
+            cols = 200
+            mid_pt = ["-1.7687", "0.0017"]
+            pt_div = "0.00008"
+            max_its = 500
+            col_palete = []
+        "#,
+        r#"
+            rows = 150
+            cols = 200
+            mid_pt = ["-0.5", "-0.5"]
+            pt_div = "0.015"
+            max_its = 200
+            formula = { type = "burning_ship" }
+            col_palete = []
+        "#,
+        r#"
+            rows = 150
+            cols = 200
+            mid_pt = ["0", "0"]
+            pt_div = "0.015"
+            max_its = 200
+            julia = true
+            julia_c = [0.3, 0.5]
+            col_palete = []
+        "#,
+    ];
+
+    // Subdivision gives the same results as calculating every point.
+    #[test]
+    fn subdivision_matches_brute_force() {
+        for view in VIEWS {
+            let mut brute_force = test_fractal(view);
+            cal_divergence(&mut brute_force);
+
+            let mut subdivided = test_fractal(view);
+            subdivided.settings.subdivision = true;
+            cal_divergence(&mut subdivided);
+
+            assert_eq!(brute_force.escape_its, subdivided.escape_its, "{}", view);
+            assert_eq!(brute_force.escape_mu, subdivided.escape_mu, "{}", view);
+        }
+    }
+
+    // Rectangles are filled inside the Mandelbrot set, but not for Julia sets.
+    #[test]
+    fn fills_inside_set_only() {
+        let filled = |view: &str| {
+            let mut fractals = test_fractal(view);
+            cal_divergence(&mut fractals);
+            let row_ims: Vec<f64> = (0..fractals.rows).map(|row| fractals.pt_lt.im - row as f64 * fractals.pt_div).collect();
+            let col_res: Vec<f64> = (0..fractals.cols).map(|col| fractals.pt_lt.re + col as f64 * fractals.pt_div).collect();
+            image_tiles(fractals.rows, fractals.cols).into_iter()
+                .flat_map(|tile| cal_tile(&fractals, tile, &row_ims, &col_res))
+                .filter(|result| result.filled)
+                .count()
+        };
+        assert!(filled(VIEWS[0]) > 0);
+        assert_eq!(filled(VIEWS[4]), 0);
+    }
+}
@@ -0,0 +1,186 @@
+// Supersampling for anti-aliased rendering.
+// Each pixel is sampled on a grid of sub-pixel points when rendering,
+// and the colours of the samples are averaged into the pixel as they
+// are calculated, so the samples for the whole image are never held.
+// Jittered patterns move each sample randomly within its grid cell,
+// which breaks up the regular pattern of aliasing. Jitter is
+// seeded from the sample position, so renders are reproducible.
+// Adaptive supersampling only re-samples high contrast pixels, where
+// the iterations count differs from a neighbour by more than a threshold,
+// other pixels keep their single sample, and have no samples calculated or held.
+
+use serde::{Deserialize, Deserializer, Serialize};
+use std::fmt;
+
+use crate::fractal::Fractal;
+
+// Largest supported supersampling factor.
+pub const MAX_FACTOR: u32 = 4;
+
+// Supersampling factor from file, checked to be in range.
+fn deserialize_factor<'de, D: Deserializer<'de>>(deserializer: D) -> Result<u32, D::Error> {
+    let factor = u32::deserialize(deserializer)?;
+    if (1..=MAX_FACTOR).contains(&factor) {
+        Ok(factor)
+    } else {
+        Err(serde::de::Error::custom(format!("Supersampling factor {} not from 1 to {}", factor, MAX_FACTOR)))
+    }
+}
+
+// Supersampling pattern.
+// Factor is samples along each side of the pixel, 1 for no supersampling.
+// Threshold is the iterations difference for adaptive supersampling.
+// This is what is saved to file under the supersample key.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(default)]
+pub struct SuperSample {
+    #[serde(deserialize_with = "deserialize_factor")]
+    pub factor: u32,
+    pub jitter: bool,
+    pub adaptive: bool,
+    pub threshold: u32,
+}
+
+impl Default for SuperSample {
+    fn default() -> Self {
+        SuperSample { factor: 1, jitter: false, adaptive: false, threshold: 0 }
+    }
+}
+
+impl SuperSample {
+    // True if pixels are sampled more than once.
+    pub fn is_on(&self) -> bool {
+        self.factor > 1
+    }
+
+    // Sample offsets within a pixel, in pixels from the pixel point.
+    // Offsets are in row order, spread over -0.5 to 0.5 either side.
+    pub fn offsets(&self, row: u32, col: u32) -> Vec<(f64, f64)> {
+        let factor = self.factor.max(1);
+        let mut offsets: Vec<(f64, f64)> = Vec::with_capacity((factor * factor) as usize);
+        for s_row in 0..factor {
+            for s_col in 0..factor {
+                // Position within the grid cell, the centre unless jittered.
+                let (pos_row, pos_col) = if self.jitter {
+                    let seed = sample_seed(row, col, s_row * factor + s_col);
+                    (unit_random(seed), unit_random(seed ^ 0x9e37_79b9_7f4a_7c15))
+                } else {
+                    (0.5, 0.5)
+                };
+                offsets.push((
+                    (s_row as f64 + pos_row) / factor as f64 - 0.5,
+                    (s_col as f64 + pos_col) / factor as f64 - 0.5,
+                ));
+            }
+        }
+        offsets
+    }
+}
+
+impl fmt::Display for SuperSample {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if !self.is_on() {
+            write!(f, "none")
+        } else {
+            write!(f, "{}x{}", self.factor, self.factor)?;
+            if self.jitter {
+                write!(f, " jittered")?;
+            }
+            if self.adaptive {
+                write!(f, " adaptive (threshold {})", self.threshold)?;
+            }
+            Ok(())
+        }
+    }
+}
+
+// Seed for the jitter of a sample, unique to the sample position.
+fn sample_seed(row: u32, col: u32, sample: u32) -> u64 {
+    ((row as u64) << 40) ^ ((col as u64) << 16) ^ sample as u64
+}
+
+// Pseudo random number from 0 to 1 for a seed (SplitMix64 finaliser).
+fn unit_random(seed: u64) -> f64 {
+    let mut x = seed.wrapping_add(0x9e37_79b9_7f4a_7c15);
+    x = (x ^ (x >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    x = (x ^ (x >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    x ^= x >> 31;
+    (x >> 11) as f64 / (1u64 << 53) as f64
+}
+
+// Pixels to re-sample in a row.
+// Every pixel unless adaptive, in which case only pixels where the
+// iterations count differs from any of the eight neighbours
+// by more than the threshold.
+pub fn resample_row(fractals: &Fractal, row: usize) -> Vec<bool> {
+    let rows = fractals.rows as usize;
+    let cols = fractals.cols as usize;
+    if !fractals.supersample.adaptive {
+        return vec![true; cols];
+    }
+
+    let threshold = fractals.supersample.threshold;
+    let its = &fractals.escape_its;
+    (0..cols).map(|col| {
+        let row_range = row.saturating_sub(1)..=(row + 1).min(rows - 1);
+        row_range.into_iter().any(|n_row| {
+            let col_range = col.saturating_sub(1)..=(col + 1).min(cols - 1);
+            col_range.into_iter().any(|n_col| its[row][col].abs_diff(its[n_row][n_col]) > threshold)
+        })
+    }).collect()
+}
+
+// Calculate the samples for a pixel being re-sampled.
+pub fn cal_pixel_samples(fractals: &Fractal, row: u32, col: u32) -> Vec<f32> {
+    fractals.supersample.offsets(row, col).into_iter()
+        .map(|offset| fractals.cal_sample_divergence(row, col, offset).mu)
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fractal::test_fractal;
+
+    #[derive(Deserialize)]
+    struct Config {
+        #[serde(default)]
+        supersample: SuperSample,
+    }
+
+    fn factor(config: &str) -> Option<u32> {
+        toml::from_str::<Config>(config).ok().map(|config| config.supersample.factor)
+    }
+
+    // Supersampling factor from file must be from 1 to MAX_FACTOR.
+    #[test]
+    fn factor_range() {
+        assert_eq!(factor(""), Some(1));
+        assert_eq!(factor("supersample = { jitter = true }"), Some(1));
+        assert_eq!(factor("supersample = { factor = 1 }"), Some(1));
+        assert_eq!(factor("supersample = { factor = 4 }"), Some(MAX_FACTOR));
+        assert_eq!(factor("supersample = { factor = 0 }"), None);
+        assert_eq!(factor("supersample = { factor = 5 }"), None);
+        assert_eq!(factor("supersample = { factor = 1000000 }"), None);
+    }
+
+    // Adaptive supersampling only re-samples pixels next to a change in iterations.
+    #[test]
+    fn adaptive_resample_row() {
+        let mut fractals = test_fractal(r#"
+            rows = 3
+            cols = 6
+            mid_pt = ["0", "0"]
+            pt_div = "0.1"
+            max_its = 100
+            col_palete = []
+            supersample = { factor = 2, adaptive = true, threshold = 2 }
+        "#);
+        fractals.init_fractal_image(fractals.rows, fractals.cols, fractals.mid_pt, fractals.pt_div);
+        fractals.escape_its = vec![vec![5, 5, 5, 5, 6, 20]; 3];
+        assert_eq!(resample_row(&fractals, 1), [false, false, false, false, true, true]);
+
+        fractals.supersample.adaptive = false;
+        assert_eq!(resample_row(&fractals, 1), [true; 6]);
+    }
+}